@@ -1,30 +1,68 @@
 use std::{
     fmt::Display,
-    sync::Arc,
+    sync::{
+        Arc,
+        RwLock,
+    },
 };
 
-use eventsource_stream::Eventsource;
 use reqwest::{
+    RequestBuilder,
     Response,
     StatusCode,
 };
 use semantica_protocol::{
     auth::{
+        AccessToken,
         AuthRequest,
         AuthResponse,
         AuthSecret,
+        Device,
+        DeviceId,
+        DevicesResponse,
+        LoginEvent,
+        LoginHistoryQuery,
+        LoginHistoryResponse,
+        NewDeviceRequest,
+        NewDeviceResponse,
         NewUserRequest,
         NewUserResponse,
+        Password,
+        RotateSecretResponse,
+        SessionId,
+        SessionInfo,
+        SessionsResponse,
+        SetPasswordRequest,
+    },
+    chat::{
+        ChatMessage,
+        MessagesResponse,
+        PostMessageRequest,
+        PostMessageResponse,
     },
     error::ApiError,
+    event::Event,
     node::{
+        CastRequest,
+        MoveRequest,
+        MoveTarget,
         NodeId,
         NodeResponse,
+        PresenceResponse,
         ResponseNode,
     },
+    pagination::Pagination,
+    spell::{
+        CraftingRequest,
+        CraftingResponse,
+        RecipesResponse,
+        SpellId,
+    },
     user::{
         InventoryResponse,
         UserId,
+        UserLink,
+        VisibilityRequest,
     },
 };
 use serde::{
@@ -39,21 +77,14 @@ use crate::{
 };
 
 /// Helper trait to turn reqwest::Response into something useful, i.e. either a
-/// parsed json value, a stream of parsed json values, or in case of an error, a
-/// useful error with status code and parsed api error.
-trait IntoApiResult: Sized {
+/// parsed json value, or in case of an error, a useful error with status code
+/// and parsed api error.
+pub(crate) trait IntoApiResult: Sized {
     async fn into_api_result(self) -> Result<Response, Error>;
 
     async fn into_api_result_json<T: for<'de> Deserialize<'de>>(self) -> Result<T, Error> {
         Ok(self.into_api_result().await?.json().await?)
     }
-
-    async fn into_api_result_stream<T: for<'de> Deserialize<'de>>(
-        self,
-    ) -> Result<EventStream<T>, Error> {
-        let stream = self.into_api_result().await?.bytes_stream().eventsource();
-        Ok(EventStream::new(stream))
-    }
 }
 
 impl IntoApiResult for Response {
@@ -93,6 +124,7 @@ impl UrlBuilder {
 pub struct Client {
     client: reqwest::Client,
     base_url: Arc<Url>,
+    access_token: Arc<RwLock<Option<AccessToken>>>,
 }
 
 impl Client {
@@ -101,9 +133,43 @@ impl Client {
         Self {
             client,
             base_url: Arc::new(base_url),
+            access_token: Default::default(),
         }
     }
 
+    /// The access token that is sent with every request.
+    ///
+    /// This is set by [`Client::login`] and [`Client::register`].
+    pub fn access_token(&self) -> Option<AccessToken> {
+        self.access_token.read().unwrap().clone()
+    }
+
+    /// Sets the access token, e.g. to reuse a token from an earlier login.
+    pub fn set_access_token(&self, access_token: Option<AccessToken>) {
+        *self.access_token.write().unwrap() = access_token;
+    }
+
+    fn authenticate(&self, request: RequestBuilder) -> RequestBuilder {
+        if let Some(access_token) = &*self.access_token.read().unwrap() {
+            request.bearer_auth(&access_token.0 .0)
+        }
+        else {
+            request
+        }
+    }
+
+    fn get(&self, url: Url) -> RequestBuilder {
+        self.authenticate(self.client.get(url))
+    }
+
+    fn post(&self, url: Url) -> RequestBuilder {
+        self.authenticate(self.client.post(url))
+    }
+
+    fn delete(&self, url: Url) -> RequestBuilder {
+        self.authenticate(self.client.delete(url))
+    }
+
     fn url(&self) -> UrlBuilder {
         UrlBuilder {
             url: Url::clone(&self.base_url),
@@ -112,19 +178,18 @@ impl Client {
 
     pub async fn register(&self, name: String) -> Result<NewUserResponse, Error> {
         let response = self
-            .client
             .post(self.url().add("register").build())
             .json(&NewUserRequest { name })
             .send()
             .await?
             .into_api_result_json::<NewUserResponse>()
             .await?;
+        self.set_access_token(Some(response.access_token.clone()));
         Ok(response)
     }
 
     pub async fn login(&self, user_id: UserId, auth_secret: AuthSecret) -> Result<(), Error> {
-        let _response = self
-            .client
+        let response = self
             .post(self.url().add("login").build())
             .json(&AuthRequest::Secret {
                 user_id,
@@ -134,25 +199,195 @@ impl Client {
             .await?
             .into_api_result_json::<AuthResponse>()
             .await?;
+        self.set_access_token(Some(response.access_token));
 
         Ok(())
     }
 
+    /// Logs in with a login name and password, that were set with
+    /// [`Client::set_password`].
+    pub async fn login_with_password(
+        &self,
+        login_name: String,
+        password: Password,
+    ) -> Result<AuthResponse, Error> {
+        let response = self
+            .post(self.url().add("login").build())
+            .json(&AuthRequest::Password {
+                login_name,
+                password,
+            })
+            .send()
+            .await?
+            .into_api_result_json::<AuthResponse>()
+            .await?;
+        self.set_access_token(Some(response.access_token.clone()));
+
+        Ok(response)
+    }
+
     pub async fn logout(&self) -> Result<(), Error> {
         let _response = self
-            .client
             .get(self.url().add("logout").build())
             .send()
             .await?
             .into_api_result()
             .await?;
+        self.set_access_token(None);
+
+        Ok(())
+    }
+
+    /// Replaces the user's auth secret with a new one.
+    ///
+    /// This logs out all other sessions, revokes all device secrets and removes
+    /// the password. The client stays logged in with a new access token.
+    pub async fn rotate_secret(&self) -> Result<AuthSecret, Error> {
+        let response = self
+            .post(self.url().add("secret").add("rotate").build())
+            .send()
+            .await?
+            .into_api_result_json::<RotateSecretResponse>()
+            .await?;
+        self.set_access_token(Some(response.access_token));
+
+        Ok(response.auth_secret)
+    }
+
+    /// Returns the user's active sessions.
+    pub async fn sessions(&self) -> Result<Vec<SessionInfo>, Error> {
+        let response = self
+            .get(self.url().add("sessions").build())
+            .send()
+            .await?
+            .into_api_result_json::<SessionsResponse>()
+            .await?;
+
+        Ok(response.sessions)
+    }
+
+    pub async fn revoke_session(&self, session_id: SessionId) -> Result<(), Error> {
+        let _response = self
+            .delete(self.url().add("sessions").add(session_id).build())
+            .send()
+            .await?
+            .into_api_result()
+            .await?;
+
+        Ok(())
+    }
+
+    /// Logs the user out everywhere, including this client.
+    pub async fn revoke_all_sessions(&self) -> Result<(), Error> {
+        let _response = self
+            .delete(self.url().add("sessions").build())
+            .send()
+            .await?
+            .into_api_result()
+            .await?;
+        self.set_access_token(None);
+
+        Ok(())
+    }
+
+    /// Returns the user's recent logins, newest first.
+    pub async fn login_history(&self, pagination: Pagination) -> Result<Vec<LoginEvent>, Error> {
+        let response = self
+            .get(self.url().add("logins").build())
+            .query(&pagination)
+            .send()
+            .await?
+            .into_api_result_json::<LoginHistoryResponse>()
+            .await?;
+
+        Ok(response.logins)
+    }
+
+    /// Returns the recent logins of all users, or of one user. Only admins can
+    /// do this.
+    pub async fn all_login_history(
+        &self,
+        user_id: Option<UserId>,
+        pagination: Pagination,
+    ) -> Result<Vec<LoginEvent>, Error> {
+        let response = self
+            .get(self.url().add("admin").add("logins").build())
+            .query(&LoginHistoryQuery { user_id })
+            .query(&pagination)
+            .send()
+            .await?
+            .into_api_result_json::<LoginHistoryResponse>()
+            .await?;
+
+        Ok(response.logins)
+    }
+
+    /// Sets a login name and password, so that the user can log in without the
+    /// auth secret.
+    pub async fn set_password(&self, login_name: String, password: Password) -> Result<(), Error> {
+        let _response = self
+            .post(self.url().add("password").build())
+            .json(&SetPasswordRequest {
+                login_name,
+                password,
+            })
+            .send()
+            .await?
+            .into_api_result()
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_password(&self) -> Result<(), Error> {
+        let _response = self
+            .delete(self.url().add("password").build())
+            .send()
+            .await?
+            .into_api_result()
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn devices(&self) -> Result<Vec<Device>, Error> {
+        let response = self
+            .get(self.url().add("devices").build())
+            .send()
+            .await?
+            .into_api_result_json::<DevicesResponse>()
+            .await?;
+
+        Ok(response.devices)
+    }
+
+    /// Creates an extra auth secret for a device, which can be revoked on its
+    /// own.
+    pub async fn create_device(&self, name: String) -> Result<NewDeviceResponse, Error> {
+        let response = self
+            .post(self.url().add("devices").build())
+            .json(&NewDeviceRequest { name })
+            .send()
+            .await?
+            .into_api_result_json::<NewDeviceResponse>()
+            .await?;
+
+        Ok(response)
+    }
+
+    pub async fn revoke_device(&self, device_id: DeviceId) -> Result<(), Error> {
+        let _response = self
+            .delete(self.url().add("devices").add(device_id).build())
+            .send()
+            .await?
+            .into_api_result()
+            .await?;
 
         Ok(())
     }
 
     pub async fn inventory(&self) -> Result<InventoryResponse, Error> {
         let response = self
-            .client
             .get(self.url().add("inventory").build())
             .send()
             .await?
@@ -161,6 +396,28 @@ impl Client {
         Ok(response)
     }
 
+    pub async fn craft(&self, ingredients: Vec<SpellId>) -> Result<CraftingResponse, Error> {
+        let response = self
+            .post(self.url().add("craft").build())
+            .json(&CraftingRequest { ingredients })
+            .send()
+            .await?
+            .into_api_result_json::<CraftingResponse>()
+            .await?;
+        Ok(response)
+    }
+
+    pub async fn recipes(&self, pagination: Pagination) -> Result<RecipesResponse, Error> {
+        let response = self
+            .get(self.url().add("recipes").build())
+            .query(&pagination)
+            .send()
+            .await?
+            .into_api_result_json::<RecipesResponse>()
+            .await?;
+        Ok(response)
+    }
+
     pub async fn node(&self, selector: NodeSelector) -> Result<ResponseNode, Error> {
         let mut url = self.url().add("node");
         match selector {
@@ -173,7 +430,6 @@ impl Client {
         }
 
         let response = self
-            .client
             .get(url.build())
             .send()
             .await?
@@ -182,6 +438,112 @@ impl Client {
 
         Ok(response.node)
     }
+
+    pub async fn cast(
+        &self,
+        node_id: NodeId,
+        position: usize,
+        spell: SpellId,
+    ) -> Result<ResponseNode, Error> {
+        let response = self
+            .post(self.url().add("node").add(node_id).add("cast").build())
+            .json(&CastRequest { position, spell })
+            .send()
+            .await?
+            .into_api_result_json::<NodeResponse>()
+            .await?;
+
+        Ok(response.node)
+    }
+
+    /// Advances the story of a node, returning its natural child.
+    pub async fn advance(&self, node_id: NodeId) -> Result<ResponseNode, Error> {
+        let response = self
+            .post(self.url().add("node").add(node_id).add("advance").build())
+            .send()
+            .await?
+            .into_api_result_json::<NodeResponse>()
+            .await?;
+
+        Ok(response.node)
+    }
+
+    /// Moves the user to an adjacent node.
+    pub async fn move_to(&self, target: MoveTarget) -> Result<ResponseNode, Error> {
+        let response = self
+            .post(self.url().add("node").add("move").build())
+            .json(&MoveRequest { target })
+            .send()
+            .await?
+            .into_api_result_json::<NodeResponse>()
+            .await?;
+
+        Ok(response.node)
+    }
+
+    /// Returns the players that were recently active in a node.
+    pub async fn presence(&self, node_id: NodeId) -> Result<Vec<UserLink>, Error> {
+        let response = self
+            .get(self.url().add("node").add(node_id).add("presence").build())
+            .send()
+            .await?
+            .into_api_result_json::<PresenceResponse>()
+            .await?;
+
+        Ok(response.users)
+    }
+
+    /// Sets whether other players can see where the user is.
+    pub async fn set_visibility(&self, visible: bool) -> Result<(), Error> {
+        let _response = self
+            .post(self.url().add("visibility").build())
+            .json(&VisibilityRequest { visible })
+            .send()
+            .await?
+            .into_api_result()
+            .await?;
+
+        Ok(())
+    }
+
+    /// Returns the chat messages of a node, newest first.
+    pub async fn messages(
+        &self,
+        node_id: NodeId,
+        pagination: Pagination,
+    ) -> Result<Vec<ChatMessage>, Error> {
+        let response = self
+            .get(self.url().add("node").add(node_id).add("chat").build())
+            .query(&pagination)
+            .send()
+            .await?
+            .into_api_result_json::<MessagesResponse>()
+            .await?;
+
+        Ok(response.messages)
+    }
+
+    /// Sends a chat message to the node the user is in.
+    pub async fn post_message(&self, node_id: NodeId, text: String) -> Result<ChatMessage, Error> {
+        let response = self
+            .post(self.url().add("node").add(node_id).add("chat").build())
+            .json(&PostMessageRequest { text })
+            .send()
+            .await?
+            .into_api_result_json::<PostMessageResponse>()
+            .await?;
+
+        Ok(response.message)
+    }
+
+    /// Subscribes to the events that are relevant to the logged in user.
+    pub fn subscribe(&self) -> EventStream<Event> {
+        EventStream::new(
+            self.client.clone(),
+            self.url().add("events").build(),
+            self.access_token.clone(),
+        )
+    }
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
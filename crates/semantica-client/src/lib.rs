@@ -4,6 +4,7 @@ mod stream;
 pub use client::Client;
 use reqwest::StatusCode;
 use semantica_protocol::error::ApiError;
+pub use stream::EventStream;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -1,53 +1,172 @@
 use std::{
-    marker::PhantomData,
     pin::Pin,
+    sync::{
+        Arc,
+        RwLock,
+    },
     task::{
         Context,
         Poll,
     },
+    time::Duration,
 };
 
 use eventsource_stream::{
     Event,
     EventStreamError,
+    Eventsource,
 };
 use futures::{
     stream::Stream,
     StreamExt,
 };
+use futures_timer::Delay;
+use semantica_protocol::auth::AccessToken;
 use serde::Deserialize;
+use url::Url;
 
 use super::Error;
+use crate::client::IntoApiResult;
+
+/// How long to wait before reconnecting, unless the server sets it with the
+/// `retry` field.
+const DEFAULT_RETRY: Duration = Duration::from_secs(1);
+
+/// The reconnect delay doubles with every failed attempt, up to this.
+const MAX_RETRY: Duration = Duration::from_secs(60);
 
+type EventSource = Pin<Box<dyn Stream<Item = Result<Event, EventStreamError<reqwest::Error>>>>>;
+
+/// A stream of server-sent events that reconnects when the connection is lost.
+///
+/// When reconnecting, the ID of the last received event is sent in the
+/// `Last-Event-ID` header, so that the server can resume where it left off.
+/// Every connection uses the client's current access token, so the stream
+/// keeps working after logging in again.
+///
+/// Connection errors are yielded and the next poll reconnects, with an
+/// exponential backoff that is reset once an event is received. If the server
+/// rejects the subscription, the error is yielded and the stream ends.
 pub struct EventStream<T> {
-    inner: Pin<Box<dyn Stream<Item = Result<Event, EventStreamError<reqwest::Error>>> + 'static>>,
-    _t: PhantomData<T>,
+    inner: Pin<Box<dyn Stream<Item = Result<T, Error>>>>,
 }
 
-impl<T> Unpin for EventStream<T> {}
-
-impl<T> EventStream<T> {
-    pub fn new(
-        stream: impl Stream<Item = Result<Event, EventStreamError<reqwest::Error>>> + 'static,
+impl<T: for<'de> Deserialize<'de> + 'static> EventStream<T> {
+    pub(crate) fn new(
+        client: reqwest::Client,
+        url: Url,
+        access_token: Arc<RwLock<Option<AccessToken>>>,
     ) -> Self {
+        let state = State {
+            client,
+            url,
+            access_token,
+            last_event_id: None,
+            events: None,
+            retry: DEFAULT_RETRY,
+            reconnect_delay: None,
+            done: false,
+        };
         Self {
-            inner: Box::pin(stream),
-            _t: PhantomData,
+            inner: Box::pin(futures::stream::unfold(state, State::next)),
         }
     }
 }
 
-impl<T: for<'de> Deserialize<'de>> Stream for EventStream<T> {
+impl<T> Stream for EventStream<T> {
     type Item = Result<T, Error>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        match self.inner.poll_next_unpin(cx) {
-            Poll::Pending => Poll::Pending,
-            Poll::Ready(None) => Poll::Ready(None),
-            Poll::Ready(Some(Err(error))) => Poll::Ready(Some(Err(error.into()))),
-            Poll::Ready(Some(Ok(event))) => {
-                let result = serde_json::from_str(&event.data).map_err(|e| Error::EventJson(e));
-                Poll::Ready(Some(result))
+        self.inner.poll_next_unpin(cx)
+    }
+}
+
+struct State {
+    client: reqwest::Client,
+    url: Url,
+    /// Shared with the client.
+    access_token: Arc<RwLock<Option<AccessToken>>>,
+    last_event_id: Option<String>,
+    events: Option<EventSource>,
+    /// The base reconnect delay.
+    retry: Duration,
+    /// How long to wait before the next reconnect. This is `None` if we haven't
+    /// lost the connection since the last event.
+    reconnect_delay: Option<Duration>,
+    done: bool,
+}
+
+impl State {
+    async fn connect(&self) -> Result<EventSource, Error> {
+        let mut request = self.client.get(self.url.clone());
+        if let Some(access_token) = &*self.access_token.read().unwrap() {
+            request = request.bearer_auth(&access_token.0 .0);
+        }
+        if let Some(last_event_id) = &self.last_event_id {
+            request = request.header("Last-Event-ID", last_event_id);
+        }
+        let response = request.send().await?.into_api_result().await?;
+        Ok(Box::pin(response.bytes_stream().eventsource()))
+    }
+
+    /// Backs off after the connection was lost or couldn't be established.
+    fn disconnected(&mut self) {
+        self.events = None;
+        self.reconnect_delay = Some(
+            self.reconnect_delay
+                .map_or(self.retry, |delay| (delay * 2).min(MAX_RETRY)),
+        );
+    }
+
+    async fn next<T: for<'de> Deserialize<'de>>(mut self) -> Option<(Result<T, Error>, Self)> {
+        loop {
+            if self.done {
+                return None;
+            }
+
+            let events = match &mut self.events {
+                Some(events) => events,
+                None => {
+                    if let Some(reconnect_delay) = self.reconnect_delay {
+                        Delay::new(reconnect_delay).await;
+                    }
+
+                    match self.connect().await {
+                        Ok(events) => self.events.insert(events),
+                        Err(error) => {
+                            // the server doesn't want us to subscribe, so there is no point in
+                            // trying again.
+                            self.done = matches!(error, Error::Api { .. });
+                            self.disconnected();
+                            return Some((Err(error), self));
+                        }
+                    }
+                }
+            };
+
+            match events.next().await {
+                Some(Ok(event)) => {
+                    self.reconnect_delay = None;
+                    if let Some(retry) = event.retry {
+                        self.retry = retry;
+                    }
+                    if !event.id.is_empty() {
+                        self.last_event_id = Some(event.id);
+                    }
+                    if event.data.is_empty() {
+                        continue;
+                    }
+                    let result = serde_json::from_str(&event.data).map_err(Error::EventJson);
+                    return Some((result, self));
+                }
+                Some(Err(error)) => {
+                    self.disconnected();
+                    return Some((Err(error.into()), self));
+                }
+                None => {
+                    // the server closed the connection, so we reconnect.
+                    self.disconnected();
+                }
             }
         }
     }
@@ -0,0 +1,357 @@
+use leptos::{
+    component,
+    create_node_ref,
+    create_rw_signal,
+    html::Input,
+    view,
+    with,
+    For,
+    IntoView,
+    RwSignal,
+    SignalGet,
+    SignalSet,
+    SignalUpdate,
+};
+use semantica_client::Client;
+use semantica_protocol::{
+    auth::{
+        Device,
+        LoginEvent,
+        LoginMethod,
+        NewDeviceResponse,
+        Password,
+        SessionInfo,
+        MIN_PASSWORD_LENGTH,
+    },
+    pagination::Pagination,
+};
+
+use super::{
+    expect_context,
+    BootstrapIcon,
+    Context,
+};
+use crate::{
+    error::Error,
+    utils::spawn_local_and_handle_error,
+};
+
+async fn load_devices(client: &Client, devices: RwSignal<Vec<Device>>) -> Result<(), Error> {
+    let response = client.devices().await?;
+    devices.set(response);
+    Ok(())
+}
+
+async fn load_sessions(client: &Client, sessions: RwSignal<Vec<SessionInfo>>) -> Result<(), Error> {
+    let response = client.sessions().await?;
+    sessions.set(response);
+    Ok(())
+}
+
+async fn load_logins(client: &Client, logins: RwSignal<Vec<LoginEvent>>) -> Result<(), Error> {
+    let response = client.login_history(Pagination::default()).await?;
+    logins.set(response);
+    Ok(())
+}
+
+/// Settings for the logged in alter: auth secret rotation, password login,
+/// sessions, device secrets and recent logins.
+#[component]
+pub fn AlterPage() -> impl IntoView {
+    let Context {
+        client,
+        logged_in_user,
+        update_user_logins,
+        ..
+    } = expect_context();
+
+    let devices = create_rw_signal(vec![]);
+    let sessions = create_rw_signal(vec![]);
+    let logins = create_rw_signal(vec![]);
+    let new_device = create_rw_signal(None::<NewDeviceResponse>);
+    let device_name_field = create_node_ref::<Input>();
+    let login_name_field = create_node_ref::<Input>();
+    let password_field = create_node_ref::<Input>();
+    let password_set = create_rw_signal(false);
+
+    {
+        let client = client.clone();
+        spawn_local_and_handle_error(async move { load_devices(&client, devices).await });
+    }
+    {
+        let client = client.clone();
+        spawn_local_and_handle_error(async move { load_sessions(&client, sessions).await });
+    }
+    {
+        let client = client.clone();
+        spawn_local_and_handle_error(async move { load_logins(&client, logins).await });
+    }
+
+    let rotate_secret = {
+        let client = client.clone();
+        move || {
+            let client = client.clone();
+            spawn_local_and_handle_error(async move {
+                let auth_secret = client.rotate_secret().await?;
+
+                // the old secret doesn't work anymore, so we need to remember the new one.
+                update_user_logins.update(move |user_logins| {
+                    if let Some(user_login) = user_logins
+                        .logged_in
+                        .and_then(|user_id| user_logins.users.get_mut(&user_id))
+                    {
+                        user_login.auth_secret = auth_secret;
+                        user_login.login_link_noticed = false;
+                    }
+                });
+
+                // the devices were revoked too.
+                devices.set(vec![]);
+                new_device.set(None);
+
+                Ok::<(), Error>(())
+            });
+        }
+    };
+
+    let create_device = {
+        let client = client.clone();
+        move |name: String| {
+            let client = client.clone();
+            spawn_local_and_handle_error(async move {
+                let response = client.create_device(name).await?;
+                devices.update(|devices| devices.push(response.device.clone()));
+                new_device.set(Some(response));
+                Ok::<(), Error>(())
+            });
+        }
+    };
+
+    let set_password = {
+        let client = client.clone();
+        move |login_name: String, password: Password| {
+            let client = client.clone();
+            spawn_local_and_handle_error(async move {
+                client.set_password(login_name, password).await?;
+                password_set.set(true);
+                Ok::<(), Error>(())
+            });
+        }
+    };
+
+    let revoke_session = {
+        let client = client.clone();
+        move |session: SessionInfo| {
+            let client = client.clone();
+            spawn_local_and_handle_error(async move {
+                client.revoke_session(session.session_id).await?;
+                sessions.update(|sessions| sessions.retain(|s| s.session_id != session.session_id));
+                Ok::<(), Error>(())
+            });
+        }
+    };
+
+    let revoke_all_sessions = {
+        let client = client.clone();
+        move || {
+            let client = client.clone();
+            spawn_local_and_handle_error(async move {
+                client.revoke_all_sessions().await?;
+                // this session was revoked too.
+                update_user_logins.update(|user_logins| user_logins.logged_in = None);
+                Ok::<(), Error>(())
+            });
+        }
+    };
+
+    let revoke_device = move |device: Device| {
+        let client = client.clone();
+        spawn_local_and_handle_error(async move {
+            client.revoke_device(device.device_id).await?;
+            devices.update(|devices| devices.retain(|d| d.device_id != device.device_id));
+            Ok::<(), Error>(())
+        });
+    };
+
+    view! {
+        <div class="w-50 m-auto p-4">
+            <h2 class="pb-4">{move || with!(|logged_in_user| logged_in_user.as_ref().map(|user_login| user_login.name.clone()))}</h2>
+
+            <h4>"Auth secret"</h4>
+            <p>"If you think someone else got your login link, you can replace your secret."</p>
+            <div class="alert alert-warning">
+                "This logs out all your other sessions, revokes all your devices and removes your password. You'll need to add your devices and set your password again."
+            </div>
+            <button
+                type="button"
+                class="btn btn-outline-danger mb-4"
+                on:click=move |_| rotate_secret()
+            >
+                "Rotate secret"
+            </button>
+
+            <h4>"Password"</h4>
+            <p>"With a login name and password, you can get back into this alter even if your browser forgets it."</p>
+            <form
+                class="vstack gap-2 mb-4"
+                on:submit=move |event| {
+                    event.prevent_default();
+                    let login_name = login_name_field.get().unwrap().value();
+                    let password_field = password_field.get().unwrap();
+                    let password = password_field.value();
+                    password_field.set_value("");
+                    password_set.set(false);
+                    set_password(login_name, Password(password.into()));
+                }
+            >
+                <input
+                    type="text"
+                    class="form-control"
+                    placeholder="Login name"
+                    autocomplete="username"
+                    required
+                    node_ref=login_name_field
+                />
+                <input
+                    type="password"
+                    class="form-control"
+                    placeholder="Password"
+                    autocomplete="new-password"
+                    minlength=MIN_PASSWORD_LENGTH
+                    required
+                    node_ref=password_field
+                />
+                <div class="d-flex flex-row align-items-center">
+                    <button type="submit" class="btn btn-secondary">"Set password"</button>
+                    {move || password_set.get().then(|| view! { <span class="ms-3 text-success">"Saved!"</span> })}
+                </div>
+            </form>
+
+            <h4>"Sessions"</h4>
+            <p>"These are the places where you're logged in."</p>
+            <ul class="list-group mb-3">
+                <For
+                    each=move || sessions.get()
+                    key=|session| session.session_id
+                    children=move |session| {
+                        let revoke_session = revoke_session.clone();
+                        let user_agent = session.user_agent.clone().unwrap_or_else(|| "Unknown".to_owned());
+                        let created_at = session.created_at.format("%Y-%m-%d %H:%M").to_string();
+                        view! {
+                            <li class="list-group-item d-flex flex-row align-items-center">
+                                <div class="me-auto">
+                                    <div class="fw-bold">
+                                        {user_agent}
+                                        {session.current.then(|| view! { <span class="badge text-bg-primary ms-2">"This session"</span> })}
+                                    </div>
+                                    <small class="text-body-secondary">"Logged in: "{created_at}</small>
+                                </div>
+                                <button
+                                    type="button"
+                                    class="btn btn-outline-danger btn-sm"
+                                    on:click=move |_| revoke_session(session.clone())
+                                >
+                                    <BootstrapIcon icon="box-arrow-right" />
+                                </button>
+                            </li>
+                        }
+                    }
+                />
+            </ul>
+            <button
+                type="button"
+                class="btn btn-outline-danger mb-4"
+                on:click=move |_| revoke_all_sessions()
+            >
+                "Log out everywhere"
+            </button>
+
+            <h4>"Devices"</h4>
+            <p>"Each device gets its own login link, which you can revoke without affecting the others."</p>
+            <ul class="list-group mb-3">
+                <For
+                    each=move || devices.get()
+                    key=|device| device.device_id
+                    children=move |device| {
+                        let revoke_device = revoke_device.clone();
+                        let last_used = device.last_used.map_or_else(|| "never".to_owned(), |last_used| last_used.format("%Y-%m-%d %H:%M").to_string());
+                        view! {
+                            <li class="list-group-item d-flex flex-row align-items-center">
+                                <div class="me-auto">
+                                    <div class="fw-bold">{device.name.clone()}</div>
+                                    <small class="text-body-secondary">"Last used: "{last_used}</small>
+                                </div>
+                                <button
+                                    type="button"
+                                    class="btn btn-outline-danger btn-sm"
+                                    on:click=move |_| revoke_device(device.clone())
+                                >
+                                    <BootstrapIcon icon="trash" />
+                                </button>
+                            </li>
+                        }
+                    }
+                />
+            </ul>
+
+            <form
+                class="input-group mb-3"
+                on:submit=move |event| {
+                    event.prevent_default();
+                    let field = device_name_field.get().unwrap();
+                    let name = field.value();
+                    if !name.trim().is_empty() {
+                        field.set_value("");
+                        create_device(name);
+                    }
+                }
+            >
+                <input
+                    type="text"
+                    class="form-control"
+                    placeholder="Device name"
+                    maxlength="64"
+                    node_ref=device_name_field
+                />
+                <button type="submit" class="btn btn-secondary">"Add device"</button>
+            </form>
+
+            {move || {
+                new_device.get().map(|NewDeviceResponse { device, auth_secret }| {
+                    let user_id = with!(|logged_in_user| logged_in_user.as_ref().map(|user_login| user_login.user_id));
+                    user_id.map(|user_id| {
+                        view! {
+                            <div class="alert alert-info">
+                                "Open this link on "{device.name}" to log in. It is only shown once."
+                                <pre class="mb-0 mt-2">{format!("/login/{user_id}/{auth_secret}")}</pre>
+                            </div>
+                        }
+                    })
+                })
+            }}
+
+            <h4>"Recent logins"</h4>
+            <p>"If you don't recognize a login, rotate your secret."</p>
+            <ul class="list-group mb-3">
+                {move || {
+                    logins.get().into_iter().map(|login| {
+                        let method = match login.method {
+                            LoginMethod::Register => "Created",
+                            LoginMethod::Secret => "Login link",
+                            LoginMethod::Device => "Device",
+                            LoginMethod::Password => "Password",
+                        };
+                        let user_agent = login.user_agent.unwrap_or_else(|| "Unknown".to_owned());
+                        let created_at = login.created_at.format("%Y-%m-%d %H:%M").to_string();
+                        view! {
+                            <li class="list-group-item">
+                                <div class="fw-bold">{created_at}" - "{method}</div>
+                                <small class="text-body-secondary">{user_agent}</small>
+                            </li>
+                        }
+                    }).collect::<Vec<_>>()
+                }}
+            </ul>
+        </div>
+    }
+}
@@ -10,10 +10,18 @@ use leptos::{
     For,
     IntoView,
     RwSignal,
+    SignalGetUntracked,
+    SignalSet,
+    SignalUpdate,
+    SignalWith,
 };
+use semantica_client::Client;
 use semantica_protocol::spell::{
+    CraftingResponse,
     ResponseSpellAmount,
     SpellId,
+    MAX_INGREDIENTS,
+    MIN_INGREDIENTS,
 };
 
 use crate::{
@@ -34,11 +42,15 @@ pub struct Inventory {
 #[derive(Copy, Clone, Debug)]
 struct GameState {
     pub inventory: RwSignal<Inventory>,
+    pub ingredients: RwSignal<Vec<SpellId>>,
+    pub last_crafted: RwSignal<Option<CraftingResponse>>,
 }
 
 fn provide_game_state() -> GameState {
     let game_state = GameState {
         inventory: create_rw_signal(Default::default()),
+        ingredients: create_rw_signal(vec![]),
+        last_crafted: create_rw_signal(None),
     };
 
     leptos::provide_context(game_state.clone());
@@ -46,37 +58,74 @@ fn provide_game_state() -> GameState {
     game_state
 }
 
-#[component]
-pub fn MainPage() -> impl IntoView {
-    let Context { client, .. } = expect_context();
-    let GameState { inventory, .. } = provide_game_state();
+async fn load_inventory(client: &Client, inventory: RwSignal<Inventory>) -> Result<(), Error> {
+    let response = client.inventory().await?;
+
+    let spells: HashMap<SpellId, ResponseSpellAmount> = response
+        .inventory
+        .into_iter()
+        .map(|spell_amount| (spell_amount.spell.spell_id, spell_amount))
+        .collect();
+
+    let mut spells_sorted = spells
+        .iter()
+        .map(|(spell_id, spell_amount)| (spell_id, &spell_amount.spell.name))
+        .collect::<Vec<_>>();
+    spells_sorted.sort_by_cached_key(|(_, name)| name.to_lowercase());
+    let spells_sorted = spells_sorted
+        .into_iter()
+        .map(|(spell_id, _)| *spell_id)
+        .collect();
+
+    update!(|inventory| {
+        inventory.spells = spells;
+        inventory.spells_sorted = spells_sorted;
+    });
+
+    Ok(())
+}
+
+fn craft(client: Client, game_state: GameState) {
+    let GameState {
+        inventory,
+        ingredients,
+        last_crafted,
+    } = game_state;
+
+    let selected = ingredients.get_untracked();
+    if selected.is_empty() {
+        return;
+    }
 
     spawn_local_and_handle_error(async move {
-        let response = client.inventory().await?;
-
-        let spells: HashMap<SpellId, ResponseSpellAmount> = response
-            .inventory
-            .into_iter()
-            .map(|spell_amount| (spell_amount.spell.spell_id, spell_amount))
-            .collect();
-
-        let mut spells_sorted = spells
-            .iter()
-            .map(|(spell_id, spell_amount)| (spell_id, &spell_amount.spell.name))
-            .collect::<Vec<_>>();
-        spells_sorted.sort_by_cached_key(|(_, name)| name.to_lowercase());
-        let spells_sorted = spells_sorted
-            .into_iter()
-            .map(|(spell_id, _)| *spell_id)
-            .collect();
-
-        update!(|inventory| {
-            inventory.spells = spells;
-            inventory.spells_sorted = spells_sorted;
-        });
+        let response = client.craft(selected).await?;
+        log::debug!("crafted: {response:?}");
+
+        ingredients.set(vec![]);
+        last_crafted.set(Some(response));
+
+        // the ingredients are used up and we got the product, so we need to reload
+        // the inventory.
+        load_inventory(&client, inventory).await?;
 
         Ok::<(), Error>(())
     });
+}
+
+#[component]
+pub fn MainPage() -> impl IntoView {
+    let Context { client, .. } = expect_context();
+    let game_state = provide_game_state();
+    let GameState {
+        inventory,
+        ingredients,
+        last_crafted,
+    } = game_state;
+
+    {
+        let client = client.clone();
+        spawn_local_and_handle_error(async move { load_inventory(&client, inventory).await });
+    }
 
     view! {
         <div class="d-flex flex-row h-100">
@@ -84,6 +133,66 @@ pub fn MainPage() -> impl IntoView {
                 <h4>"TODO"</h4>
             </div>
             <div class="d-flex flex-column w-25 h-100 border-start">
+                <div class="d-flex flex-wrap align-items-center p-2 border-bottom">
+                    {move || {
+                        inventory.with(|inventory| {
+                            ingredients.with(|selected| {
+                                selected
+                                    .iter()
+                                    .enumerate()
+                                    .filter_map(|(index, spell_id)| {
+                                        let spell_amount = inventory.spells.get(spell_id)?;
+                                        let emoji = spell_amount.spell.emoji.clone();
+                                        let name = spell_amount.spell.name.clone();
+                                        Some(view!{
+                                            <button
+                                                type="button"
+                                                class="btn btn-outline-secondary m-1 py-1"
+                                                on:click=move |_| {
+                                                    ingredients.update(|selected| {
+                                                        selected.remove(index);
+                                                    });
+                                                }
+                                            >
+                                                {emoji}
+                                                {name}
+                                            </button>
+                                        })
+                                    })
+                                    .collect::<Vec<_>>()
+                            })
+                        })
+                    }}
+                    <button
+                        type="button"
+                        class="btn btn-primary m-1 py-1 ms-auto"
+                        disabled=move || with!(|ingredients| ingredients.len() < MIN_INGREDIENTS)
+                        on:click=move |_| craft(client.clone(), game_state)
+                    >
+                        "Combine"
+                    </button>
+                </div>
+                {move || {
+                    with!(|last_crafted| {
+                        last_crafted.as_ref().map(|response| {
+                            let message = if response.first_discovery {
+                                "You discovered"
+                            }
+                            else {
+                                "You crafted"
+                            };
+                            view!{
+                                <div class="p-2 border-bottom">
+                                    {message}
+                                    " "
+                                    {response.product.emoji.clone()}
+                                    " "
+                                    {response.product.name.clone()}
+                                </div>
+                            }
+                        })
+                    })
+                }}
                 <form class="position-relative">
                     <input
                         class="form-control"
@@ -98,17 +207,33 @@ pub fn MainPage() -> impl IntoView {
                 </form>
                 <div class="d-flex flex-wrap p-2">
                     <For
-                        each=move || with!(|inventory| inventory.spells_sorted.clone())
-                        key=|id| *id
-                        children=move |id| {
+                        // keyed by amount too, so that the buttons are updated when the amounts change.
+                        each=move || {
+                            with!(|inventory| {
+                                inventory.spells_sorted
+                                    .iter()
+                                    .map(|id| (*id, inventory.spells[id].amount))
+                                    .collect::<Vec<_>>()
+                            })
+                        }
+                        key=|item| *item
+                        children=move |(id, amount)| {
                             with!(|inventory| {
                                 let spell_amount = inventory.spells.get(&id).unwrap();
                                 view!{
                                     <button
                                         type="button"
                                         class="btn btn-secondary m-1 py-1"
+                                        on:click=move |_| {
+                                            ingredients.update(|selected| {
+                                                let already_selected = selected.iter().filter(|selected_id| **selected_id == id).count();
+                                                if selected.len() < MAX_INGREDIENTS && already_selected < amount {
+                                                    selected.push(id);
+                                                }
+                                            });
+                                        }
                                     >
-                                        {spell_amount.amount}
+                                        {amount}
                                         <BootstrapIcon icon="x" />
                                         {spell_amount.spell.emoji.clone()}
                                         {spell_amount.spell.name.clone()}
@@ -1,5 +1,7 @@
 use leptos::{
     component,
+    create_node_ref,
+    html::Input,
     spawn_local,
     view,
     with,
@@ -8,6 +10,7 @@ use leptos::{
     Params,
     SignalGet,
     SignalUpdate,
+    SignalWithUntracked,
 };
 use leptos_router::{
     use_navigate,
@@ -15,7 +18,10 @@ use leptos_router::{
     Params,
 };
 use semantica_protocol::{
-    auth::AuthSecret,
+    auth::{
+        AuthSecret,
+        Password,
+    },
     user::UserId,
 };
 
@@ -57,6 +63,50 @@ fn login(user_id: UserId, auth_secret: AuthSecret) {
     });
 }
 
+fn login_with_password(login_name: String, password: Password) {
+    log::debug!("login with password: {login_name}");
+
+    spawn_local_and_handle_error(async move {
+        let Context { client, .. } = expect_context();
+        let Storage {
+            value: user_logins,
+            update_value: update_user_logins,
+            ..
+        } = use_user_logins();
+
+        let response = client.login_with_password(login_name, password).await?;
+
+        // we need an auth secret to remember this alter, but the main one can't be
+        // recovered. so unless this browser already remembers it, we give it its own
+        // device secret.
+        let is_known = user_logins
+            .with_untracked(|user_logins| user_logins.users.contains_key(&response.user_id));
+        let new_user_login = if is_known {
+            None
+        }
+        else {
+            let device = client.create_device("Password login".to_owned()).await?;
+            Some(UserLogin {
+                user_id: response.user_id,
+                name: response.name,
+                auth_secret: device.auth_secret,
+                login_link_noticed: false,
+            })
+        };
+
+        update_user_logins.update(move |user_logins| {
+            user_logins.logged_in = Some(response.user_id);
+            if let Some(user_login) = new_user_login {
+                user_logins.users.insert(user_login.user_id, user_login);
+            }
+        });
+
+        use_navigate()("/", Default::default());
+
+        Ok::<(), Error>(())
+    });
+}
+
 #[component]
 pub fn LoginPage() -> impl IntoView {
     let Storage {
@@ -108,10 +158,54 @@ pub fn LoginPage() -> impl IntoView {
                     "Create a new alter"
                 </button>
             </div>
+
+            <PasswordLogin />
         </div>
     }
 }
 
+#[component]
+fn PasswordLogin() -> impl IntoView {
+    let login_name_field = create_node_ref::<Input>();
+    let password_field = create_node_ref::<Input>();
+
+    view! {
+        <p class="pt-4">"Or log in with your login name and password."</p>
+
+        <form
+            class="vstack gap-2"
+            on:submit=move |event| {
+                event.prevent_default();
+
+                let login_name = login_name_field.get().unwrap().value();
+                let password_field = password_field.get().unwrap();
+                let password = password_field.value();
+                password_field.set_value("");
+
+                if !login_name.is_empty() && !password.is_empty() {
+                    login_with_password(login_name, Password(password.into()));
+                }
+            }
+        >
+            <input
+                type="text"
+                class="form-control"
+                placeholder="Login name"
+                autocomplete="username"
+                node_ref=login_name_field
+            />
+            <input
+                type="password"
+                class="form-control"
+                placeholder="Password"
+                autocomplete="current-password"
+                node_ref=password_field
+            />
+            <button type="submit" class="btn btn-outline-secondary">"Log in"</button>
+        </form>
+    }
+}
+
 #[component]
 pub fn LoginWithUrl() -> impl IntoView {
     #[derive(Clone, Params, PartialEq)]
@@ -1,3 +1,4 @@
+pub mod alter;
 pub mod game;
 pub mod index;
 pub mod login;
@@ -37,6 +38,7 @@ use semantica_client::Client;
 use url::Url;
 
 use self::{
+    alter::AlterPage,
     index::IndexPage,
     login::{
         LoginPage,
@@ -192,6 +194,9 @@ pub fn App() -> impl IntoView {
                                     let client = client.clone();
                                     is_logged_in.get().then(move || {
                                         view!{
+                                            <A class="nav-link" href="/alter">
+                                                <BootstrapIcon icon="gear-fill" />
+                                            </A>
                                             <button
                                                 type="button"
                                                 class="nav-link btn btn-link"
@@ -235,6 +240,14 @@ pub fn App() -> impl IntoView {
                             }
                         } />
                         <Route path="/login/:user_id/:auth_secret" view=LoginWithUrl />
+                        <Route path="/alter" view=move || {
+                            if is_logged_in.get() {
+                                view!{ <AlterPage /> }.into_view()
+                            }
+                            else {
+                                view!{ <Redirect path="/" /> }.into_view()
+                            }
+                        } />
                     </Routes>
                 </main>
             </div>
@@ -1,11 +1,19 @@
 use std::fmt::Debug;
 
+use chrono::{
+    DateTime,
+    Utc,
+};
 use serde::{
     Deserialize,
     Serialize,
 };
+use uuid::Uuid;
 
-use crate::user::UserId;
+use crate::user::{
+    UserId,
+    UserLink,
+};
 
 /// Generic wrapper for secrets.
 ///
@@ -36,20 +44,51 @@ impl<T> Debug for Secret<T> {
 #[serde(transparent)]
 pub struct AuthSecret(pub Secret<String>);
 
+/// An opaque token that authenticates requests with an `Authorization: Bearer`
+/// header.
+#[derive(
+    Clone, Debug, PartialEq, Serialize, Deserialize, derive_more::Display, derive_more::FromStr,
+)]
+#[serde(transparent)]
+pub struct AccessToken(pub Secret<String>);
+
+/// A password that the user chose, see [`SetPasswordRequest`].
+#[derive(
+    Clone, Debug, PartialEq, Serialize, Deserialize, derive_more::Display, derive_more::FromStr,
+)]
+#[serde(transparent)]
+pub struct Password(pub Secret<String>);
+
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+pub const MAX_PASSWORD_LENGTH: usize = 256;
+
+pub const MIN_LOGIN_NAME_LENGTH: usize = 3;
+pub const MAX_LOGIN_NAME_LENGTH: usize = 32;
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum AuthRequest {
     Secret {
         user_id: UserId,
         auth_secret: AuthSecret,
     },
+    /// Only works if the user set a password with [`SetPasswordRequest`].
+    ///
+    /// Login names are case-insensitive.
+    Password {
+        login_name: String,
+        password: Password,
+    },
 }
 
 /// # Note
 ///
-/// the secret to authenticate further requests is returned as a cookie.
+/// Browsers can authenticate further requests with the session cookie that is
+/// set on login. Other clients can use the access token instead.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AuthResponse {
     pub user_id: UserId,
+    pub name: String,
+    pub access_token: AccessToken,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -61,4 +100,145 @@ pub struct NewUserRequest {
 pub struct NewUserResponse {
     pub user_id: UserId,
     pub auth_secret: AuthSecret,
+    pub access_token: AccessToken,
+}
+
+/// Attaches a login name and password to the user, so that they can log in
+/// without their auth secret.
+///
+/// Login names are unique, may only contain ASCII letters, digits, `_`, `-` and
+/// `.`, and are stored in lower case.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SetPasswordRequest {
+    pub login_name: String,
+    pub password: Password,
+}
+
+/// Issues a new auth secret and invalidates the old one, and all sessions,
+/// access tokens and device secrets. The password is removed too.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RotateSecretResponse {
+    pub auth_secret: AuthSecret,
+    pub access_token: AccessToken,
+}
+
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    derive_more::From,
+    derive_more::FromStr,
+    derive_more::Display,
+)]
+#[serde(transparent)]
+pub struct DeviceId(pub Uuid);
+
+/// An extra auth secret that a user gave to one of their devices.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Device {
+    pub device_id: DeviceId,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DevicesResponse {
+    pub devices: Vec<Device>,
+}
+
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    derive_more::From,
+    derive_more::FromStr,
+    derive_more::Display,
+)]
+#[serde(transparent)]
+pub struct SessionId(pub Uuid);
+
+/// A login of the user, i.e. an access token or a session cookie.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub session_id: SessionId,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub user_agent: Option<String>,
+    /// The device secret that was used to log in, if any.
+    pub device_id: Option<DeviceId>,
+    /// Whether this is the session that made the request.
+    pub current: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SessionsResponse {
+    pub sessions: Vec<SessionInfo>,
+}
+
+/// How a user logged in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoginMethod {
+    /// The user was just created.
+    Register,
+    /// With the user's auth secret.
+    Secret,
+    /// With a device secret.
+    Device,
+    /// With login name and password.
+    Password,
+}
+
+/// A successful login.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LoginEvent {
+    pub user: UserLink,
+    pub method: LoginMethod,
+    pub device_id: Option<DeviceId>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Recent logins, newest first.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LoginHistoryResponse {
+    pub logins: Vec<LoginEvent>,
+}
+
+/// Query parameters for the login history of all users, which only admins can
+/// see.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct LoginHistoryQuery {
+    /// Only show logins of this user.
+    #[serde(default)]
+    pub user_id: Option<UserId>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NewDeviceRequest {
+    pub name: String,
+}
+
+/// # Note
+///
+/// The auth secret for the device is only returned once.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NewDeviceResponse {
+    pub device: Device,
+    pub auth_secret: AuthSecret,
 }
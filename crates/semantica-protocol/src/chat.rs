@@ -0,0 +1,61 @@
+use chrono::{
+    DateTime,
+    Utc,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use uuid::Uuid;
+
+use crate::{
+    node::NodeId,
+    user::UserLink,
+};
+
+/// Maximum length of a chat message in characters.
+pub const MAX_MESSAGE_LENGTH: usize = 500;
+
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    derive_more::From,
+    derive_more::FromStr,
+    derive_more::Display,
+)]
+#[serde(transparent)]
+pub struct MessageId(pub Uuid);
+
+/// A message that a player sent to everyone in a node.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub message_id: MessageId,
+    pub node_id: NodeId,
+    pub author: UserLink,
+    pub text: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PostMessageRequest {
+    pub text: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PostMessageResponse {
+    pub message: ChatMessage,
+}
+
+/// Chat messages, newest first.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MessagesResponse {
+    pub messages: Vec<ChatMessage>,
+}
@@ -3,7 +3,7 @@ use serde::{
     Serialize,
 };
 
-#[derive(Debug, thiserror::Error, Serialize, Deserialize)]
+#[derive(Clone, Debug, thiserror::Error, Serialize, Deserialize)]
 pub enum ApiError {
     #[error("unknown")]
     Unknown,
@@ -19,4 +19,46 @@ pub enum ApiError {
 
     #[error("not authenticated")]
     NotAuthenticated,
+
+    #[error("invalid atom position")]
+    InvalidAtomPosition,
+
+    #[error("node is not adjacent")]
+    NotAdjacent,
+
+    #[error("missing ingredients")]
+    MissingIngredients,
+
+    #[error("the user doesn't have this spell")]
+    MissingSpell,
+
+    #[error("invalid number of ingredients")]
+    InvalidIngredients,
+
+    #[error("text generation failed")]
+    GenerationFailed,
+
+    #[error("invalid chat message")]
+    InvalidMessage,
+
+    #[error("the user is not in this node")]
+    NotInNode,
+
+    #[error("too many requests")]
+    RateLimited,
+
+    #[error("invalid name")]
+    InvalidName,
+
+    #[error("invalid login name")]
+    InvalidLoginName,
+
+    #[error("login name is already taken")]
+    LoginNameTaken,
+
+    #[error("password is too short or too long")]
+    InvalidPassword,
+
+    #[error("only admins can do this")]
+    NotAdmin,
 }
@@ -0,0 +1,56 @@
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    chat::ChatMessage,
+    node::NodeId,
+    spell::{
+        RecipeId,
+        Spell,
+    },
+    user::{
+        UserId,
+        UserLink,
+    },
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// A spell was crafted for the first time.
+    SpellDiscovered {
+        spell: Spell<UserLink>,
+        recipe_id: RecipeId,
+    },
+
+    /// A new child node was created.
+    NodeCreated { node_id: NodeId, parent_id: NodeId },
+
+    /// A player moved into a node, or became visible in it.
+    ///
+    /// Hidden players are only shown their own movements.
+    PlayerEntered {
+        node_id: NodeId,
+        user: UserLink,
+        hidden: bool,
+    },
+
+    /// A player moved out of a node, or became invisible in it.
+    PlayerLeft {
+        node_id: NodeId,
+        user: UserLink,
+        hidden: bool,
+    },
+
+    /// A player sent a chat message to a node.
+    ChatMessage { message: ChatMessage },
+
+    /// The contents of a player's inventory changed.
+    InventoryChanged { user_id: UserId },
+
+    /// Some events were lost, e.g. because the client was away for longer than
+    /// events are kept. The client should fetch everything it shows again.
+    Resync,
+}
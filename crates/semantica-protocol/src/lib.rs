@@ -1,6 +1,9 @@
 pub mod auth;
+pub mod chat;
 pub mod error;
+pub mod event;
 pub mod node;
+pub mod pagination;
 pub mod spell;
 pub mod user;
 
@@ -94,7 +94,7 @@ pub struct ForkLink<ForkSpell: Links<SpellId>> {
     pub fork: Fork<ForkSpell>,
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Fork<Spell: Links<SpellId>> {
     pub position: usize,
     pub spell: Spell,
@@ -105,6 +105,23 @@ pub struct Content {
     pub paragraphs: Vec<Paragraph>,
 }
 
+impl Content {
+    /// Iterates over all atoms in this content, paragraph by paragraph.
+    ///
+    /// The index of an atom in this iterator is its position, as used by
+    /// [`Fork::position`].
+    pub fn atoms(&self) -> impl Iterator<Item = (&Paragraph, &Atom)> {
+        self.paragraphs
+            .iter()
+            .flat_map(|paragraph| paragraph.atoms.iter().map(move |atom| (paragraph, atom)))
+    }
+
+    pub fn atom_text(&self, position: usize) -> Option<&str> {
+        let (paragraph, atom) = self.atoms().nth(position)?;
+        paragraph.text.get(atom.start..atom.start + atom.length)
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Paragraph {
     pub text: String,
@@ -123,3 +140,28 @@ pub struct Atom {
 pub struct NodeResponse {
     pub node: ResponseNode,
 }
+
+/// The players that were recently active in a node.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PresenceResponse {
+    pub users: Vec<UserLink>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CastRequest {
+    pub position: usize,
+    pub spell: SpellId,
+}
+
+/// Where a player can move to from the node they're currently in.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum MoveTarget {
+    Parent,
+    NaturalChild,
+    ForkChild(NodeId),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MoveRequest {
+    pub target: MoveTarget,
+}
@@ -0,0 +1,31 @@
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+/// Query parameters for endpoints that return their results in pages.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Pagination {
+    #[serde(default)]
+    pub offset: usize,
+
+    #[serde(default = "Pagination::default_limit")]
+    pub limit: usize,
+}
+
+impl Pagination {
+    pub const DEFAULT_LIMIT: usize = 50;
+
+    fn default_limit() -> usize {
+        Self::DEFAULT_LIMIT
+    }
+}
+
+impl Default for Pagination {
+    fn default() -> Self {
+        Self {
+            offset: 0,
+            limit: Self::DEFAULT_LIMIT,
+        }
+    }
+}
@@ -16,16 +16,40 @@ use crate::{
     Links,
 };
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize, derive_more::From)]
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    derive_more::From,
+    derive_more::FromStr,
+    derive_more::Display,
+)]
+#[serde(transparent)]
 pub struct RecipeId(pub Uuid);
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Recipe {
+pub struct Recipe<Spell: Links<SpellId>> {
     pub recipe_id: RecipeId,
-    pub product: Option<SpellId>,
-    pub ingredients: Vec<SpellId>,
-    pub created_at: DateTime<Utc>,
-    pub created_by: UserLink,
+    pub ingredients: Vec<Spell>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub product: Option<Spell>,
+}
+
+pub type ResponseRecipe = Recipe<Spell<UserLink>>;
+
+/// A recipe that a user has discovered.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KnownRecipe {
+    pub recipe: ResponseRecipe,
+    pub discovered_at: DateTime<Utc>,
 }
 
 #[derive(
@@ -76,12 +100,24 @@ impl<Spell: Links<SpellId>> Links<SpellId> for SpellAmount<Spell> {
 
 pub type ResponseSpellAmount = SpellAmount<Spell<UserLink>>;
 
+/// How many ingredients a recipe needs at least.
+pub const MIN_INGREDIENTS: usize = 2;
+
+/// How many ingredients a recipe can have at most.
+pub const MAX_INGREDIENTS: usize = 2;
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CraftingRequest {
     pub ingredients: Vec<SpellId>,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CraftingResponse {
     pub product: Spell<UserLink>,
     pub first_discovery: bool,
 }
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecipesResponse {
+    pub recipes: Vec<KnownRecipe>,
+}
@@ -62,3 +62,9 @@ impl Links<UserId> for UserLink {
 pub struct InventoryResponse {
     pub inventory: Vec<SpellAmount<Spell<UserLink>>>,
 }
+
+/// Sets whether other players can see where the user is.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VisibilityRequest {
+    pub visible: bool,
+}
@@ -0,0 +1 @@
+DROP INDEX IF EXISTS index_nodes_fork;
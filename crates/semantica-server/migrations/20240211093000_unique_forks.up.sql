@@ -0,0 +1,2 @@
+-- casting the same spell on the same atom of a node always leads to the same fork.
+CREATE UNIQUE INDEX index_nodes_fork ON nodes(parent_id, parent_position, created_with) WHERE parent_position IS NOT NULL;
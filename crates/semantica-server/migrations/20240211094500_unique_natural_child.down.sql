@@ -0,0 +1,2 @@
+DROP INDEX index_nodes_natural_parent;
+CREATE INDEX index_nodes_natural_parent ON nodes(parent_id) WHERE parent_position IS NULL;
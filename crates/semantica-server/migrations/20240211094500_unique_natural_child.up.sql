@@ -0,0 +1,3 @@
+-- a node has at most one natural child.
+DROP INDEX index_nodes_natural_parent;
+CREATE UNIQUE INDEX index_nodes_natural_parent ON nodes(parent_id) WHERE parent_position IS NULL;
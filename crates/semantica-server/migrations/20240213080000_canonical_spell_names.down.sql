@@ -0,0 +1,2 @@
+DROP INDEX index_spells_canonical_name;
+ALTER TABLE spells DROP COLUMN canonical_name;
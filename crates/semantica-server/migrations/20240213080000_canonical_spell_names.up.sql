@@ -0,0 +1,6 @@
+-- spells are identified by their canonical name: lowercase with collapsed whitespace.
+-- the canonical names of existing spells are set by the server when it starts, since it
+-- also has to merge duplicate spells and derive new recipe ids.
+ALTER TABLE spells ADD COLUMN canonical_name TEXT;
+
+CREATE UNIQUE INDEX index_spells_canonical_name ON spells(canonical_name);
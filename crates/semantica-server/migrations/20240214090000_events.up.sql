@@ -0,0 +1,9 @@
+-- append-only log of game events, so that clients can catch up after reconnecting.
+CREATE TABLE events (
+    event_id BIGSERIAL NOT NULL PRIMARY KEY,
+    transaction_id UUID NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    data JSONB NOT NULL
+);
+
+CREATE INDEX index_events_created_at ON events(created_at);
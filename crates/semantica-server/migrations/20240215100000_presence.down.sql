@@ -0,0 +1,3 @@
+DROP INDEX index_users_in_node_last_active;
+ALTER TABLE users DROP COLUMN visible;
+ALTER TABLE users DROP COLUMN last_active;
@@ -0,0 +1,9 @@
+-- when the user last made a request, so that we can tell who is around.
+ALTER TABLE users ADD COLUMN last_active TIMESTAMP;
+UPDATE users SET last_active = last_login;
+ALTER TABLE users ALTER COLUMN last_active SET NOT NULL;
+
+-- whether other users can see where this user is.
+ALTER TABLE users ADD COLUMN visible BOOLEAN NOT NULL DEFAULT TRUE;
+
+CREATE INDEX index_users_in_node_last_active ON users(in_node, last_active);
@@ -0,0 +1,10 @@
+CREATE TABLE chat_messages (
+    message_id UUID NOT NULL PRIMARY KEY,
+    node_id UUID NOT NULL REFERENCES nodes(node_id),
+    user_id UUID NOT NULL REFERENCES users(user_id),
+    text TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX index_chat_messages_node_id_created_at ON chat_messages(node_id, created_at);
+CREATE INDEX index_chat_messages_user_id_created_at ON chat_messages(user_id, created_at);
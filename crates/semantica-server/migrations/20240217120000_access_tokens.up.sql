@@ -0,0 +1,10 @@
+-- tokens for clients that can't use the session cookie. only the sha256 hash of
+-- the token is stored.
+CREATE TABLE access_tokens (
+    token_hash BYTEA NOT NULL PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(user_id),
+    created_at TIMESTAMP NOT NULL,
+    expires_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX index_access_tokens_user_id ON access_tokens(user_id);
@@ -0,0 +1,3 @@
+DROP INDEX index_access_tokens_device_id;
+ALTER TABLE access_tokens DROP COLUMN device_id;
+DROP TABLE device_secrets;
@@ -0,0 +1,17 @@
+-- extra secrets that users can give to other devices, and revoke individually.
+CREATE TABLE device_secrets (
+    device_id UUID NOT NULL PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(user_id),
+    name TEXT NOT NULL,
+    secret_hash BYTEA NOT NULL UNIQUE,
+    created_at TIMESTAMP NOT NULL,
+    last_used TIMESTAMP
+);
+
+CREATE INDEX index_device_secrets_user_id ON device_secrets(user_id);
+
+-- revoking a device also revokes the access tokens it was used to get.
+ALTER TABLE access_tokens
+    ADD COLUMN device_id UUID REFERENCES device_secrets(device_id) ON DELETE CASCADE;
+
+CREATE INDEX index_access_tokens_device_id ON access_tokens(device_id);
@@ -0,0 +1,4 @@
+DROP TABLE failed_logins;
+DROP INDEX index_users_login_name;
+ALTER TABLE users DROP COLUMN password_hash;
+ALTER TABLE users DROP COLUMN login_name;
@@ -0,0 +1,14 @@
+-- optional login name and password. login names are stored in lower case.
+ALTER TABLE users ADD COLUMN login_name TEXT;
+ALTER TABLE users ADD COLUMN password_hash TEXT;
+
+CREATE UNIQUE INDEX index_users_login_name ON users(login_name);
+
+-- failed password logins, for rate limiting. this is keyed by the login name,
+-- since it might not belong to any user.
+CREATE TABLE failed_logins (
+    login_name TEXT NOT NULL,
+    attempted_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX index_failed_logins_login_name ON failed_logins(login_name, attempted_at);
@@ -0,0 +1,3 @@
+DROP INDEX index_access_tokens_session_id;
+ALTER TABLE access_tokens DROP COLUMN user_agent;
+ALTER TABLE access_tokens DROP COLUMN session_id;
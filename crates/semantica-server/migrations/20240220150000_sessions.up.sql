@@ -0,0 +1,6 @@
+-- access tokens double as sessions, which users can list and revoke. the
+-- session ID identifies them without revealing the token hash.
+ALTER TABLE access_tokens ADD COLUMN session_id UUID NOT NULL DEFAULT gen_random_uuid();
+ALTER TABLE access_tokens ADD COLUMN user_agent TEXT;
+
+CREATE UNIQUE INDEX index_access_tokens_session_id ON access_tokens(session_id);
@@ -0,0 +1,13 @@
+-- successful logins, so that users and admins can spot stolen login links.
+CREATE TABLE login_events (
+    login_event_id BIGSERIAL PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(user_id),
+    -- register, secret, device or password
+    method TEXT NOT NULL,
+    device_id UUID REFERENCES device_secrets(device_id) ON DELETE SET NULL,
+    user_agent TEXT,
+    created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX index_login_events_user_id ON login_events(user_id, created_at);
+CREATE INDEX index_login_events_created_at ON login_events(created_at);
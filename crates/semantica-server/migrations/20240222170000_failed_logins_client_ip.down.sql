@@ -0,0 +1,3 @@
+DROP INDEX index_failed_logins_login_name;
+ALTER TABLE failed_logins DROP COLUMN client_ip;
+CREATE INDEX index_failed_logins_login_name ON failed_logins(login_name, attempted_at);
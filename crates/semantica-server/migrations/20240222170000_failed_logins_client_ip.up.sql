@@ -0,0 +1,7 @@
+-- failed logins are counted per login name and client IP, so that nobody can
+-- lock other users out by failing to log in as them.
+DELETE FROM failed_logins;
+ALTER TABLE failed_logins ADD COLUMN client_ip TEXT NOT NULL;
+
+DROP INDEX index_failed_logins_login_name;
+CREATE INDEX index_failed_logins_login_name ON failed_logins(login_name, client_ip, attempted_at);
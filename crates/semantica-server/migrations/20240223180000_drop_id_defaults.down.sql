@@ -0,0 +1 @@
+-- the ids never had defaults, so there's nothing to restore.
@@ -0,0 +1,4 @@
+-- spell and recipe ids are always derived from names and ingredients. databases that were
+-- migrated by a development version might still have random defaults for them.
+ALTER TABLE spells ALTER COLUMN spell_id DROP DEFAULT;
+ALTER TABLE recipes ALTER COLUMN recipe_id DROP DEFAULT;
@@ -0,0 +1,4 @@
+-- whether the other players were told that the user is in their node. users that aren't
+-- active anymore are announced as leaving.
+ALTER TABLE users ADD COLUMN present BOOLEAN NOT NULL DEFAULT TRUE;
+UPDATE users SET present = FALSE WHERE last_active < utc_now() - INTERVAL '5 minutes';
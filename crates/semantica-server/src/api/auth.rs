@@ -1,10 +1,26 @@
+use std::net::{
+    IpAddr,
+    SocketAddr,
+};
+
 use async_trait::async_trait;
 use axum::{
     extract::{
+        ConnectInfo,
         FromRequestParts,
+        Path,
+        Query,
         State,
     },
-    http::request::Parts,
+    http::{
+        header::{
+            AUTHORIZATION,
+            USER_AGENT,
+        },
+        request::Parts,
+        HeaderMap,
+        HeaderName,
+    },
     Json,
 };
 use rand::{
@@ -14,55 +30,116 @@ use rand::{
 };
 use semantica_protocol::{
     auth::{
+        AccessToken,
         AuthRequest,
         AuthResponse,
+        DeviceId,
+        DevicesResponse,
+        LoginHistoryQuery,
+        LoginHistoryResponse,
+        LoginMethod,
+        NewDeviceRequest,
+        NewDeviceResponse,
         NewUserRequest,
         NewUserResponse,
+        RotateSecretResponse,
         Secret,
+        SessionId,
+        SessionsResponse,
+        SetPasswordRequest,
+        MAX_PASSWORD_LENGTH,
+        MIN_PASSWORD_LENGTH,
     },
     error::ApiError,
-    user::UserId,
+    event::Event,
+    pagination::Pagination,
+    user::{
+        UserId,
+        UserLink,
+    },
 };
 use tower_sessions::Session;
-use uuid::{
-    uuid,
-    Uuid,
-};
+use uuid::Uuid;
 
 use crate::{
     error::Error,
     game::{
-        auth::create_auth_secret,
+        auth::{
+            canonicalize_login_name,
+            create_auth_secret,
+            Login,
+        },
         Game,
+        TEST_USER_ID,
     },
 };
 
+/// The session stores an access token, so that sessions can be revoked like
+/// access tokens.
+const SESSION_ACCESS_TOKEN: &str = "access_token";
+
+const MAX_DEVICE_NAME_LENGTH: usize = 64;
+
+const MAX_USER_AGENT_LENGTH: usize = 256;
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
 pub async fn login(
     State(game): State<Game>,
+    ConnectInfo(peer_address): ConnectInfo<SocketAddr>,
     session: Session,
+    headers: HeaderMap,
     Json(auth_request): Json<AuthRequest>,
 ) -> Result<Json<AuthResponse>, Error> {
     let mut transaction = game.transaction().await?;
-    let auth_result = transaction.authenticate_user(auth_request).await?;
+    let client_ip = client_ip(&game, &headers, peer_address);
+    let user_agent = user_agent(&headers);
+    let Some(login) = transaction
+        .authenticate_user(auth_request, client_ip, user_agent.as_deref())
+        .await?
+    else {
+        // commit anyway, so that failed password logins are counted.
+        transaction.commit().await?;
+        return Err(ApiError::AuthenticationFailed.into());
+    };
+    let user = transaction.fetch_user_link(login.user_id).await?;
+    let access_token = transaction
+        .insert_access_token(login, user_agent.as_deref())
+        .await?;
     transaction.commit().await?;
 
-    if let Some(user_id) = auth_result {
-        session.insert("user_id", user_id).await?;
-        Ok(Json(AuthResponse { user_id }))
-    }
-    else {
-        Err(ApiError::AuthenticationFailed.into())
-    }
+    session.insert(SESSION_ACCESS_TOKEN, &access_token).await?;
+
+    Ok(Json(AuthResponse {
+        user_id: login.user_id,
+        name: user.name,
+        access_token,
+    }))
 }
 
-pub async fn logout(session: Session) -> Result<(), Error> {
-    session.remove::<UserId>("user_id").await?;
+pub async fn logout(
+    State(game): State<Game>,
+    session: Session,
+    headers: HeaderMap,
+) -> Result<(), Error> {
+    let session_access_token = session.remove::<AccessToken>(SESSION_ACCESS_TOKEN).await?;
+
+    let mut transaction = game.transaction().await?;
+    for access_token in [bearer_token(&headers)?, session_access_token]
+        .into_iter()
+        .flatten()
+    {
+        transaction.delete_access_token(&access_token).await?;
+    }
+    transaction.commit().await?;
+
     Ok(())
 }
 
 pub async fn register(
     State(game): State<Game>,
     session: Session,
+    headers: HeaderMap,
     Json(new_user_request): Json<NewUserRequest>,
 ) -> Result<Json<NewUserResponse>, Error> {
     let mut transaction = game.transaction().await?;
@@ -71,16 +148,268 @@ pub async fn register(
     transaction
         .insert_user(user_id, &new_user_request.name, auth_secret.clone())
         .await?;
+    let node_id = transaction.fetch_user_node_id(user_id).await?;
+    transaction.publish(Event::PlayerEntered {
+        node_id,
+        user: UserLink {
+            user_id,
+            name: new_user_request.name,
+        },
+        hidden: false,
+    });
+    let login = Login {
+        user_id,
+        method: LoginMethod::Register,
+        device_id: None,
+    };
+    let user_agent = user_agent(&headers);
+    transaction
+        .insert_login_event(&login, user_agent.as_deref())
+        .await?;
+    let access_token = transaction
+        .insert_access_token(login, user_agent.as_deref())
+        .await?;
     transaction.commit().await?;
-    session.insert("user_id", user_id).await?;
+    session.insert(SESSION_ACCESS_TOKEN, &access_token).await?;
 
     Ok(Json(NewUserResponse {
         user_id,
         auth_secret,
+        access_token,
+    }))
+}
+
+pub async fn rotate_secret(
+    State(game): State<Game>,
+    Authenticated(user_id): Authenticated,
+    session: Session,
+    headers: HeaderMap,
+) -> Result<Json<RotateSecretResponse>, Error> {
+    let mut transaction = game.transaction().await?;
+    let auth_secret = transaction.rotate_auth_secret(user_id).await?;
+    // all sessions were invalidated, but we keep this one logged in.
+    let access_token = transaction
+        .insert_access_token(
+            Login {
+                user_id,
+                method: LoginMethod::Secret,
+                device_id: None,
+            },
+            user_agent(&headers).as_deref(),
+        )
+        .await?;
+    transaction.commit().await?;
+
+    session.insert(SESSION_ACCESS_TOKEN, &access_token).await?;
+
+    Ok(Json(RotateSecretResponse {
+        auth_secret,
+        access_token,
+    }))
+}
+
+pub async fn set_password(
+    State(game): State<Game>,
+    Authenticated(user_id): Authenticated,
+    Json(set_password_request): Json<SetPasswordRequest>,
+) -> Result<(), Error> {
+    let login_name = canonicalize_login_name(&set_password_request.login_name)
+        .ok_or(ApiError::InvalidLoginName)?;
+
+    let password_length = set_password_request.password.0 .0.chars().count();
+    if !(MIN_PASSWORD_LENGTH..=MAX_PASSWORD_LENGTH).contains(&password_length) {
+        return Err(ApiError::InvalidPassword.into());
+    }
+
+    let mut transaction = game.transaction().await?;
+    transaction
+        .set_password(user_id, &login_name, set_password_request.password)
+        .await?;
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+pub async fn delete_password(
+    State(game): State<Game>,
+    Authenticated(user_id): Authenticated,
+) -> Result<(), Error> {
+    let mut transaction = game.transaction().await?;
+    transaction.delete_password(user_id).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+pub async fn get_sessions(
+    State(game): State<Game>,
+    Authenticated(user_id): Authenticated,
+    session: Session,
+    headers: HeaderMap,
+) -> Result<Json<SessionsResponse>, Error> {
+    let current = match bearer_token(&headers)? {
+        Some(access_token) => Some(access_token),
+        None => session.get::<AccessToken>(SESSION_ACCESS_TOKEN).await?,
+    };
+
+    let mut transaction = game.transaction().await?;
+    let sessions = transaction
+        .fetch_sessions(user_id, current.as_ref())
+        .await?;
+    transaction.commit().await?;
+
+    Ok(Json(SessionsResponse { sessions }))
+}
+
+pub async fn revoke_session(
+    State(game): State<Game>,
+    Authenticated(user_id): Authenticated,
+    Path(session_id): Path<SessionId>,
+) -> Result<(), Error> {
+    let mut transaction = game.transaction().await?;
+    if !transaction.delete_session(user_id, session_id).await? {
+        return Err(ApiError::NotFound.into());
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Logs the user out everywhere, including this session.
+pub async fn revoke_all_sessions(
+    State(game): State<Game>,
+    Authenticated(user_id): Authenticated,
+    session: Session,
+) -> Result<(), Error> {
+    let mut transaction = game.transaction().await?;
+    transaction.delete_sessions(user_id).await?;
+    transaction.commit().await?;
+
+    session.remove::<AccessToken>(SESSION_ACCESS_TOKEN).await?;
+
+    Ok(())
+}
+
+pub async fn get_login_history(
+    State(game): State<Game>,
+    Authenticated(user_id): Authenticated,
+    Query(pagination): Query<Pagination>,
+) -> Result<Json<LoginHistoryResponse>, Error> {
+    let mut transaction = game.transaction().await?;
+    let logins = transaction
+        .fetch_login_events(Some(user_id), pagination)
+        .await?;
+    transaction.commit().await?;
+    Ok(Json(LoginHistoryResponse { logins }))
+}
+
+/// Returns the logins of all users, or of one user, for admins.
+pub async fn get_all_login_history(
+    State(game): State<Game>,
+    Authenticated(user_id): Authenticated,
+    Query(login_history_query): Query<LoginHistoryQuery>,
+    Query(pagination): Query<Pagination>,
+) -> Result<Json<LoginHistoryResponse>, Error> {
+    let mut transaction = game.transaction().await?;
+    if !transaction.fetch_user_god_mode(user_id).await? {
+        return Err(ApiError::NotAdmin.into());
+    }
+    let logins = transaction
+        .fetch_login_events(login_history_query.user_id, pagination)
+        .await?;
+    transaction.commit().await?;
+    Ok(Json(LoginHistoryResponse { logins }))
+}
+
+pub async fn get_devices(
+    State(game): State<Game>,
+    Authenticated(user_id): Authenticated,
+) -> Result<Json<DevicesResponse>, Error> {
+    let mut transaction = game.transaction().await?;
+    let devices = transaction.fetch_devices(user_id).await?;
+    transaction.commit().await?;
+    Ok(Json(DevicesResponse { devices }))
+}
+
+pub async fn create_device(
+    State(game): State<Game>,
+    Authenticated(user_id): Authenticated,
+    Json(new_device_request): Json<NewDeviceRequest>,
+) -> Result<Json<NewDeviceResponse>, Error> {
+    let name = new_device_request.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_DEVICE_NAME_LENGTH {
+        return Err(ApiError::InvalidName.into());
+    }
+
+    let mut transaction = game.transaction().await?;
+    let (device, auth_secret) = transaction.insert_device(user_id, name.to_owned()).await?;
+    transaction.commit().await?;
+
+    Ok(Json(NewDeviceResponse {
+        device,
+        auth_secret,
     }))
 }
 
-/// extracts the UserId from the session
+pub async fn revoke_device(
+    State(game): State<Game>,
+    Authenticated(user_id): Authenticated,
+    Path(device_id): Path<DeviceId>,
+) -> Result<(), Error> {
+    let mut transaction = game.transaction().await?;
+    if !transaction.delete_device(user_id, device_id).await? {
+        return Err(ApiError::NotFound.into());
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Returns the `User-Agent` header, so that users can tell their sessions
+/// apart.
+fn user_agent(headers: &HeaderMap) -> Option<String> {
+    let user_agent = headers.get(USER_AGENT)?.to_str().ok()?;
+    Some(user_agent.chars().take(MAX_USER_AGENT_LENGTH).collect())
+}
+
+/// Returns the IP address of the client.
+///
+/// Behind a reverse proxy, this is the last address in `X-Forwarded-For`, since
+/// that is the one the proxy added. Otherwise the header can't be trusted.
+fn client_ip(game: &Game, headers: &HeaderMap, peer_address: SocketAddr) -> IpAddr {
+    game.behind_proxy()
+        .then(|| {
+            headers
+                .get_all(X_FORWARDED_FOR)
+                .iter()
+                .next_back()?
+                .to_str()
+                .ok()?
+                .rsplit(',')
+                .next()?
+                .trim()
+                .parse()
+                .ok()
+        })
+        .flatten()
+        .unwrap_or_else(|| peer_address.ip())
+}
+
+/// Returns the access token from the `Authorization` header, if there is one.
+fn bearer_token(headers: &HeaderMap) -> Result<Option<AccessToken>, Error> {
+    let Some(header) = headers.get(AUTHORIZATION)
+    else {
+        return Ok(None);
+    };
+
+    let access_token = header
+        .to_str()
+        .ok()
+        .and_then(|header| header.strip_prefix("Bearer "))
+        .ok_or(ApiError::NotAuthenticated)?;
+
+    Ok(Some(AccessToken(Secret(access_token.trim().to_owned()))))
+}
+
+/// extracts the UserId from the access token in the `Authorization` header or
+/// the session
 pub struct Authenticated(pub UserId);
 
 #[async_trait]
@@ -88,19 +417,40 @@ impl FromRequestParts<Game> for Authenticated {
     type Rejection = Error;
 
     async fn from_request_parts(parts: &mut Parts, state: &Game) -> Result<Self, Error> {
-        let get_user_id = move || {
+        // an invalid access token is an error, even if there is a session.
+        let access_token = bearer_token(&parts.headers)?;
+
+        let get_session_access_token = move || {
             async move {
                 let session = Session::from_request_parts(parts, state).await.ok()?;
-                session.get("user_id").await.ok().flatten()
+                session.get(SESSION_ACCESS_TOKEN).await.ok().flatten()
             }
         };
 
-        Ok(Self(
-            get_user_id()
-                .await
-                //.ok_or_else(|| ApiError::NotAuthenticated)?,
-                .unwrap_or_else(|| uuid!("43d65ac1-2778-49e8-b28d-65c7334cec32").into()),
-        ))
+        let access_token = match access_token {
+            Some(access_token) => Some(access_token),
+            None => get_session_access_token().await,
+        };
+
+        let mut transaction = state.transaction().await?;
+
+        let user_id = if let Some(access_token) = access_token {
+            transaction
+                .authenticate_access_token(&access_token)
+                .await?
+                .ok_or(ApiError::NotAuthenticated)?
+        }
+        else if state.dev_mode() {
+            TEST_USER_ID.into()
+        }
+        else {
+            return Err(ApiError::NotAuthenticated.into());
+        };
+
+        transaction.touch_user(user_id).await?;
+        transaction.commit().await?;
+
+        Ok(Self(user_id))
     }
 }
 
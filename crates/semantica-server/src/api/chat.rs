@@ -0,0 +1,135 @@
+use axum::{
+    extract::{
+        Path,
+        Query,
+        State,
+    },
+    Json,
+};
+use semantica_protocol::{
+    chat::{
+        MessagesResponse,
+        PostMessageRequest,
+        PostMessageResponse,
+        MAX_MESSAGE_LENGTH,
+    },
+    error::ApiError,
+    event::Event,
+    node::NodeId,
+    pagination::Pagination,
+};
+
+use super::auth::Authenticated;
+use crate::{
+    error::Error,
+    game::Game,
+};
+
+pub async fn get_messages(
+    State(game): State<Game>,
+    Authenticated(user_id): Authenticated,
+    Path(node_id): Path<NodeId>,
+    Query(pagination): Query<Pagination>,
+) -> Result<Json<MessagesResponse>, Error> {
+    let mut transaction = game.transaction().await?;
+
+    // players can only read the chat of the node they're in.
+    if transaction.fetch_user_node_id(user_id).await? != node_id {
+        return Err(ApiError::NotInNode.into());
+    }
+
+    let messages = transaction.fetch_chat_messages(node_id, pagination).await?;
+    transaction.commit().await?;
+    Ok(Json(MessagesResponse { messages }))
+}
+
+pub async fn post_message(
+    State(game): State<Game>,
+    Authenticated(user_id): Authenticated,
+    Path(node_id): Path<NodeId>,
+    Json(post_message_request): Json<PostMessageRequest>,
+) -> Result<Json<PostMessageResponse>, Error> {
+    let text = post_message_request.text.trim();
+    if text.is_empty() || text.chars().count() > MAX_MESSAGE_LENGTH {
+        return Err(ApiError::InvalidMessage.into());
+    }
+
+    let mut transaction = game.transaction().await?;
+
+    // players can only talk to the node they're in.
+    if transaction.fetch_user_node_id(user_id).await? != node_id {
+        return Err(ApiError::NotInNode.into());
+    }
+
+    transaction.check_chat_rate_limit(user_id).await?;
+
+    let author = transaction.fetch_user_link(user_id).await?;
+    let message = transaction
+        .insert_chat_message(node_id, author, text.to_owned())
+        .await?;
+    transaction.publish(Event::ChatMessage {
+        message: message.clone(),
+    });
+    transaction.commit().await?;
+
+    Ok(Json(PostMessageResponse { message }))
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::PgPool;
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[sqlx::test(migrations = false)]
+    async fn it_only_allows_chatting_in_the_users_node(pool: PgPool) {
+        let game = Game::new_for_test(pool).await.unwrap();
+        let (user_id, _) = game.create_user_for_test("alice").await.unwrap();
+
+        let mut transaction = game.transaction().await.unwrap();
+        let node_id = transaction.fetch_user_node_id(user_id).await.unwrap();
+        transaction.commit().await.unwrap();
+
+        let Json(posted) = post_message(
+            State(game.clone()),
+            Authenticated(user_id),
+            Path(node_id),
+            Json(PostMessageRequest {
+                text: "hello".to_owned(),
+            }),
+        )
+        .await
+        .unwrap();
+        let Json(messages) = get_messages(
+            State(game.clone()),
+            Authenticated(user_id),
+            Path(node_id),
+            Query(Pagination::default()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(messages.messages.len(), 1);
+        assert_eq!(messages.messages[0].message_id, posted.message.message_id);
+
+        let other_node_id = NodeId(Uuid::new_v4());
+        let result = post_message(
+            State(game.clone()),
+            Authenticated(user_id),
+            Path(other_node_id),
+            Json(PostMessageRequest {
+                text: "hello".to_owned(),
+            }),
+        )
+        .await;
+        assert!(matches!(result, Err(Error::Api(ApiError::NotInNode))));
+        let result = get_messages(
+            State(game),
+            Authenticated(user_id),
+            Path(other_node_id),
+            Query(Pagination::default()),
+        )
+        .await;
+        assert!(matches!(result, Err(Error::Api(ApiError::NotInNode))));
+    }
+}
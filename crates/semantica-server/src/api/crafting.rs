@@ -1,25 +1,39 @@
+use std::collections::BTreeMap;
+
 use axum::{
-    extract::State,
+    extract::{
+        Query,
+        State,
+    },
     Json,
 };
-use chrono::{
-    TimeZone,
-    Utc,
-};
 use semantica_protocol::{
+    error::ApiError,
+    event::Event,
+    pagination::Pagination,
     spell::{
         CraftingRequest,
         CraftingResponse,
-        Spell,
+        RecipesResponse,
+        SpellAmount,
         SpellId,
+        MAX_INGREDIENTS,
+        MIN_INGREDIENTS,
     },
-    user::UserLink,
+    user::UserId,
 };
 
 use super::auth::Authenticated;
 use crate::{
     error::Error,
-    game::Game,
+    game::{
+        recipe::{
+            get_recipe_id_for_ingredients,
+            CraftedProduct,
+        },
+        Game,
+        Transaction,
+    },
 };
 
 pub async fn craft(
@@ -27,123 +41,188 @@ pub async fn craft(
     Authenticated(user_id): Authenticated,
     Json(crafting_request): Json<CraftingRequest>,
 ) -> Result<Json<CraftingResponse>, Error> {
+    if !(MIN_INGREDIENTS..=MAX_INGREDIENTS).contains(&crafting_request.ingredients.len()) {
+        return Err(ApiError::InvalidIngredients.into());
+    }
+
+    let ingredients = crafting_request.ingredients;
+    let recipe_id = get_recipe_id_for_ingredients(&ingredients);
+
+    // check the ingredients before generating anything. they're only taken out of
+    // the inventory once we know the product, so that no rows are locked while we
+    // wait for the AI.
+    let mut transaction = game.transaction().await?;
+    remove_ingredients(&mut transaction, user_id, &ingredients).await?;
+    let product_id = transaction.fetch_recipe_product(recipe_id).await?;
+    transaction.rollback().await?;
+
+    let crafted_product = match product_id {
+        Some(product_id) => {
+            CraftedProduct {
+                product_id,
+                discovered_by: None,
+            }
+        }
+        None => {
+            game.craft_product(recipe_id, ingredients.clone(), user_id)
+                .await?
+        }
+    };
+
     let mut transaction = game.transaction().await?;
 
-    let mut ingredients = crafting_request
-        .ingredients
-        .into_iter()
-        .map(|spell_id| spell_id.0)
-        .collect::<Vec<_>>();
-    ingredients.sort();
-
-    let row = sqlx::query!(
-        r#"
-        SELECT
-            spells.spell_id AS spell_id,
-            spells.name AS spell_name,
-            spells.emoji AS spell_emoji,
-            spells.description AS spell_description,
-            spells.created_at AS spell_created_at,
-            spells.created_by AS spell_created_by,
-            users.user_id AS "created_by?",
-            users.name AS "created_by_name?"
-        FROM recipes
-            INNER JOIN spells ON recipes.product = spells.spell_id
-            LEFT OUTER JOIN users ON spells.created_by = users.user_id
-        WHERE recipes.ingredients = $1
-        "#,
-        &ingredients
-    )
-    .fetch_optional(transaction.db())
-    .await?;
-
-    if let Some(row) = row {
-        Ok(Json(CraftingResponse {
-            product: Spell {
-                spell_id: row.spell_id.into(),
-                name: row.spell_name,
-                emoji: row.spell_emoji,
-                description: row.spell_description,
-                created_at: row
-                    .spell_created_at
-                    .as_ref()
-                    .map(|t| Utc.from_utc_datetime(t)),
-                created_by: match (row.created_by, row.created_by_name) {
-                    (Some(user_id), Some(name)) => {
-                        Some(UserLink {
-                            user_id: user_id.into(),
-                            name,
-                        })
-                    }
-                    _ => None,
-                },
+    // the ingredients might be gone by now, in which case the transaction is
+    // rolled back.
+    remove_ingredients(&mut transaction, user_id, &ingredients).await?;
+
+    let crafting_response = CraftingResponse {
+        product: transaction.fetch_spell(crafted_product.product_id).await?,
+        first_discovery: crafted_product.discovered_by == Some(user_id),
+    };
+
+    transaction.insert_known_recipe(user_id, recipe_id).await?;
+
+    transaction
+        .add_to_inventory(
+            user_id,
+            SpellAmount {
+                spell: crafting_response.product.spell_id,
+                amount: 1,
             },
-            first_discovery: false,
-        }))
-    }
-    else {
-        let mut rows = sqlx::query!(
-            "SELECT spell_id, name FROM spells WHERE spell_id = ANY($1)",
-            &ingredients
-        )
-        .fetch_all(transaction.db())
-        .await?;
-        rows.sort_by_key(|row| row.spell_id);
-        let ingredient_names = rows.iter().map(|row| row.name.as_str()).collect::<Vec<_>>();
-
-        let crafting_result = game.ai().craft(&ingredient_names).await?;
-
-        let created_at = Utc::now();
-
-        let row = sqlx::query!(
-            r#"
-            INSERT INTO spells
-                (name, emoji, description, created_at, created_by)
-            VALUES
-                ($1, $2, $3, $4, $5)
-            RETURNING spell_id
-            "#,
-            crafting_result.name,
-            crafting_result.emoji,
-            crafting_result.description,
-            created_at.naive_utc(),
-            user_id.0,
-        )
-        .fetch_one(transaction.db())
-        .await?;
-        let spell_id: SpellId = row.spell_id.into();
-
-        sqlx::query!(
-            r#"
-            INSERT INTO recipes
-                (product, ingredients)
-            VALUES
-                ($1, $2)
-            "#,
-            spell_id.0,
-            &ingredients
         )
-        .execute(transaction.db())
         .await?;
 
-        let row = sqlx::query!("SELECT name FROM users WHERE user_id = $1", user_id.0)
-            .fetch_one(transaction.db())
-            .await?;
-        let user_name = row.name;
-
-        Ok(Json(CraftingResponse {
-            product: Spell {
-                spell_id,
-                name: crafting_result.name,
-                emoji: crafting_result.emoji,
-                description: crafting_result.description,
-                created_at: Some(created_at),
-                created_by: Some(UserLink {
+    transaction.publish(Event::InventoryChanged { user_id });
+
+    transaction.commit().await?;
+
+    Ok(Json(crafting_response))
+}
+
+/// Takes the ingredients out of the user's inventory.
+async fn remove_ingredients(
+    transaction: &mut Transaction<'_>,
+    user_id: UserId,
+    ingredients: &[SpellId],
+) -> Result<(), Error> {
+    let mut ingredient_amounts = BTreeMap::new();
+    for spell_id in ingredients {
+        *ingredient_amounts.entry(*spell_id).or_insert(0) += 1;
+    }
+    for (spell, amount) in ingredient_amounts {
+        transaction
+            .remove_from_inventory(user_id, SpellAmount { spell, amount })
+            .await?
+            .ok_or(ApiError::MissingIngredients)?;
+    }
+    Ok(())
+}
+
+pub async fn get_recipes(
+    State(game): State<Game>,
+    Authenticated(user_id): Authenticated,
+    Query(pagination): Query<Pagination>,
+) -> Result<Json<RecipesResponse>, Error> {
+    let mut transaction = game.transaction().await?;
+    let recipes = transaction.fetch_known_recipes(user_id, pagination).await?;
+    transaction.commit().await?;
+    Ok(Json(RecipesResponse { recipes }))
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::game::spell::get_spell_id_for_name;
+
+    async fn give_spells(game: &Game, user_id: UserId, spell_ids: &[SpellId]) {
+        let mut transaction = game.transaction().await.unwrap();
+        for spell_id in spell_ids {
+            transaction
+                .add_to_inventory(
                     user_id,
-                    name: user_name,
+                    SpellAmount {
+                        spell: *spell_id,
+                        amount: 1,
+                    },
+                )
+                .await
+                .unwrap();
+        }
+        transaction.commit().await.unwrap();
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_requires_all_ingredients(pool: PgPool) {
+        let game = Game::new_for_test(pool).await.unwrap();
+        let (user_id, _) = game.create_user_for_test("alice").await.unwrap();
+        let fire = get_spell_id_for_name("Fire");
+        let water = get_spell_id_for_name("Water");
+
+        // the user has one fire, but needs two, and no water at all.
+        give_spells(&game, user_id, &[fire]).await;
+        for ingredients in [vec![fire, water], vec![fire, fire]] {
+            let result = craft(
+                State(game.clone()),
+                Authenticated(user_id),
+                Json(CraftingRequest { ingredients }),
+            )
+            .await;
+            assert!(matches!(
+                result,
+                Err(Error::Api(ApiError::MissingIngredients))
+            ));
+        }
+
+        // nothing was taken out of the inventory, and nothing was crafted.
+        let mut transaction = game.transaction().await.unwrap();
+        let inventory = transaction.fetch_inventory(user_id).await.unwrap();
+        let recipes = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM recipes"#)
+            .fetch_one(transaction.db())
+            .await
+            .unwrap();
+        transaction.commit().await.unwrap();
+        assert_eq!(inventory.len(), 1);
+        assert_eq!(inventory[0].amount, 1);
+        assert_eq!(recipes, 0);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_reuses_recipes(pool: PgPool) {
+        let game = Game::new_for_test(pool).await.unwrap();
+        let fire = get_spell_id_for_name("Fire");
+        let water = get_spell_id_for_name("Water");
+
+        let mut products = vec![];
+        for (name, ingredients) in [("alice", [fire, water]), ("bob", [water, fire])] {
+            let (user_id, _) = game.create_user_for_test(name).await.unwrap();
+            give_spells(&game, user_id, &ingredients).await;
+
+            let Json(crafting_response) = craft(
+                State(game.clone()),
+                Authenticated(user_id),
+                Json(CraftingRequest {
+                    ingredients: ingredients.to_vec(),
                 }),
-            },
-            first_discovery: true,
-        }))
+            )
+            .await
+            .unwrap();
+            products.push(crafting_response);
+        }
+
+        // the order of the ingredients doesn't matter, and only the first crafter
+        // discovers the product.
+        assert_eq!(products[0].product.spell_id, products[1].product.spell_id);
+        assert!(products[0].first_discovery);
+        assert!(!products[1].first_discovery);
+
+        let mut transaction = game.transaction().await.unwrap();
+        let recipes = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM recipes"#)
+            .fetch_one(transaction.db())
+            .await
+            .unwrap();
+        transaction.commit().await.unwrap();
+        assert_eq!(recipes, 1);
     }
 }
@@ -1,7 +1,13 @@
+use std::collections::VecDeque;
+
 use axum::{
     extract::State,
+    http::HeaderMap,
     response::{
-        sse,
+        sse::{
+            self,
+            KeepAlive,
+        },
         Sse,
     },
 };
@@ -9,28 +15,188 @@ use futures::{
     Stream,
     StreamExt,
 };
-use serde::{
-    Deserialize,
-    Serialize,
+use semantica_protocol::{
+    event::Event,
+    user::UserId,
+};
+use tokio::sync::broadcast::{
+    self,
+    error::RecvError,
 };
 
+use super::auth::Authenticated;
 use crate::{
     error::Error,
-    game::Game,
+    game::{
+        event::{
+            EventFilter,
+            EventId,
+        },
+        Game,
+    },
 };
 
-#[derive(Debug, Serialize, Deserialize)]
-pub enum Event {
-    // todo
-}
+/// How many logged events are fetched at once when replaying.
+const REPLAY_BATCH_SIZE: usize = 100;
 
 pub async fn subscribe(
-    State(_game): State<Game>,
-) -> Sse<impl Stream<Item = Result<sse::Event, Error>>> {
-    // todo: subscribe to events
-    let stream = futures::stream::empty::<Event>();
+    State(game): State<Game>,
+    Authenticated(user_id): Authenticated,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<sse::Event, Error>>>, Error> {
+    // if the client was subscribed before, we replay what it missed from the event
+    // log.
+    let last_event_id = headers
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok());
+
+    let subscription = Subscription::new(game, user_id, last_event_id).await?;
+
+    let stream = futures::stream::unfold(subscription, Subscription::next).map(|result| {
+        let (event_id, event) = result?;
+        Ok(sse::Event::default()
+            .id(event_id.to_string())
+            .json_data(event)?)
+    });
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+struct Subscription {
+    game: Game,
+    user_id: UserId,
+    receiver: broadcast::Receiver<(EventId, Event)>,
+    filter: EventFilter,
+    /// The last event that was sent to the client.
+    last_event_id: EventId,
+    /// Whether events are read from the event log instead of the receiver.
+    replaying: bool,
+    /// Replayed events that weren't sent yet.
+    buffer: VecDeque<(EventId, Event)>,
+}
+
+impl Subscription {
+    async fn new(
+        game: Game,
+        user_id: UserId,
+        last_event_id: Option<EventId>,
+    ) -> Result<Self, Error> {
+        // subscribe before we look up where the user is, so that we don't miss them
+        // moving.
+        let receiver = game.subscribe();
+
+        let mut transaction = game.transaction().await?;
+        let latest_event_id = transaction.fetch_latest_event_id().await?;
+        let in_node = transaction.fetch_user_node_id(user_id).await?;
+        transaction.commit().await?;
+
+        Ok(Self {
+            game,
+            user_id,
+            receiver,
+            filter: EventFilter::new(user_id, in_node),
+            last_event_id: last_event_id.unwrap_or(latest_event_id),
+            replaying: last_event_id.is_some(),
+            buffer: VecDeque::new(),
+        })
+    }
+
+    async fn next(mut self) -> Option<(Result<(EventId, Event), Error>, Self)> {
+        loop {
+            let (event_id, event) = if let Some(event) = self.buffer.pop_front() {
+                event
+            }
+            else if self.replaying {
+                match self.fetch_replay_batch().await {
+                    Ok(events) => {
+                        // once we've caught up, we switch to live events.
+                        self.replaying = !events.is_empty();
+                        self.buffer.extend(events);
+                        continue;
+                    }
+                    Err(error) => return Some((Err(error), self)),
+                }
+            }
+            else {
+                match self.receiver.recv().await {
+                    Ok((event_id, event)) => {
+                        // skip live events that were already replayed.
+                        if event_id <= self.last_event_id {
+                            continue;
+                        }
+                        (event_id, event)
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        // the skipped events are still in the event log.
+                        tracing::warn!(skipped, "event subscriber lagged");
+                        self.replaying = true;
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            };
+
+            self.last_event_id = event_id;
+
+            if self.filter.is_relevant(&event) {
+                return Some((Ok((event_id, event)), self));
+            }
+        }
+    }
+
+    async fn fetch_replay_batch(&mut self) -> Result<Vec<(EventId, Event)>, Error> {
+        let mut transaction = self.game.transaction().await?;
+
+        if transaction.fetch_deleted_event_id().await? > self.last_event_id {
+            // events the client hasn't seen were deleted, so we can't replay them.
+            // instead we tell the client to start over from the current state.
+            let latest_event_id = transaction.fetch_latest_event_id().await?;
+            let in_node = transaction.fetch_user_node_id(self.user_id).await?;
+            transaction.commit().await?;
+
+            self.filter = EventFilter::new(self.user_id, in_node);
+            return Ok(vec![(latest_event_id, Event::Resync)]);
+        }
+
+        let events = transaction
+            .fetch_events_after(self.last_event_id, REPLAY_BATCH_SIZE)
+            .await?;
+        transaction.commit().await?;
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use sqlx::PgPool;
+
+    use super::*;
+
+    #[sqlx::test(migrations = false)]
+    async fn it_resyncs_clients_whose_events_were_deleted(pool: PgPool) {
+        let game = Game::new_for_test(pool).await.unwrap();
+        let (user_id, _) = game.create_user_for_test("alice").await.unwrap();
+
+        let mut transaction = game.transaction().await.unwrap();
+        transaction.publish(Event::InventoryChanged { user_id });
+        transaction.commit().await.unwrap();
 
-    let stream = stream.map(|event| Ok(sse::Event::default().json_data(event)?));
+        let mut transaction = game.transaction().await.unwrap();
+        let now = transaction.now();
+        transaction
+            .delete_events_before(now + Duration::minutes(1))
+            .await
+            .unwrap();
+        let latest_event_id = transaction.fetch_latest_event_id().await.unwrap();
+        transaction.commit().await.unwrap();
 
-    Sse::new(stream)
+        // the client last saw an event before the deleted one.
+        let subscription = Subscription::new(game, user_id, Some(0)).await.unwrap();
+        let (result, _) = subscription.next().await.unwrap();
+        let (event_id, event) = result.unwrap();
+        assert!(matches!(event, Event::Resync));
+        assert_eq!(event_id, latest_event_id);
+    }
 }
@@ -1,13 +1,16 @@
 pub mod auth;
+pub mod chat;
 pub mod crafting;
 pub mod events;
 pub mod inventory;
 pub mod node;
+pub mod presence;
 
 use axum::{
     http::StatusCode,
     routing::{
         any,
+        delete,
         get,
         post,
     },
@@ -30,6 +33,19 @@ impl AsStatusCode for ApiError {
             ApiError::AuthenticationFailed | ApiError::NotAuthenticated => StatusCode::UNAUTHORIZED,
             ApiError::Unknown | ApiError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
             ApiError::NotFound => StatusCode::NOT_FOUND,
+            ApiError::InvalidAtomPosition
+            | ApiError::NotAdjacent
+            | ApiError::MissingIngredients
+            | ApiError::MissingSpell
+            | ApiError::InvalidIngredients
+            | ApiError::InvalidMessage
+            | ApiError::InvalidName
+            | ApiError::InvalidLoginName
+            | ApiError::InvalidPassword => StatusCode::BAD_REQUEST,
+            ApiError::LoginNameTaken => StatusCode::CONFLICT,
+            ApiError::NotInNode | ApiError::NotAdmin => StatusCode::FORBIDDEN,
+            ApiError::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::GenerationFailed => StatusCode::BAD_GATEWAY,
         }
     }
 }
@@ -40,9 +56,34 @@ pub fn routes() -> Router<Game> {
         .route("/login", post(auth::login))
         .route("/logout", get(auth::logout))
         .route("/register", post(auth::register))
+        .route("/secret/rotate", post(auth::rotate_secret))
+        .route(
+            "/password",
+            post(auth::set_password).delete(auth::delete_password),
+        )
+        .route(
+            "/sessions",
+            get(auth::get_sessions).delete(auth::revoke_all_sessions),
+        )
+        .route("/sessions/:session_id", delete(auth::revoke_session))
+        .route("/logins", get(auth::get_login_history))
+        .route("/admin/logins", get(auth::get_all_login_history))
+        .route("/devices", get(auth::get_devices).post(auth::create_device))
+        .route("/devices/:device_id", delete(auth::revoke_device))
         .route("/inventory", get(inventory::get_inventory))
+        .route("/craft", post(crafting::craft))
+        .route("/recipes", get(crafting::get_recipes))
         .route("/node/current", get(node::current_node))
+        .route("/node/move", post(node::move_to))
         .route("/node/:node_id", get(node::get_node))
+        .route("/node/:node_id/cast", post(node::cast))
+        .route("/node/:node_id/advance", post(node::advance))
+        .route("/node/:node_id/presence", get(presence::get_presence))
+        .route(
+            "/node/:node_id/chat",
+            get(chat::get_messages).post(chat::post_message),
+        )
+        .route("/visibility", post(presence::set_visibility))
         .route("/events", get(events::subscribe))
         .fallback(any(not_found))
 }
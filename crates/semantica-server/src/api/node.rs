@@ -5,15 +5,31 @@ use axum::{
     },
     Json,
 };
-use semantica_protocol::node::{
-    NodeId,
-    NodeResponse,
+use semantica_protocol::{
+    error::ApiError,
+    event::Event,
+    node::{
+        CastRequest,
+        Fork,
+        MoveRequest,
+        NodeId,
+        NodeResponse,
+    },
+    spell::{
+        SpellAmount,
+        SpellId,
+    },
+    user::UserId,
 };
 
 use super::auth::Authenticated;
 use crate::{
     error::Error,
-    game::Game,
+    game::{
+        node::ChildKey,
+        Game,
+        Transaction,
+    },
 };
 
 pub async fn current_node(
@@ -26,6 +42,31 @@ pub async fn current_node(
     Ok(Json(NodeResponse { node }))
 }
 
+pub async fn move_to(
+    State(game): State<Game>,
+    Authenticated(user_id): Authenticated,
+    Json(move_request): Json<MoveRequest>,
+) -> Result<Json<NodeResponse>, Error> {
+    let mut transaction = game.transaction().await?;
+    let (from, to) = transaction.move_user(user_id, move_request.target).await?;
+    let user = transaction.fetch_user_link(user_id).await?;
+    let hidden = !transaction.fetch_user_visible(user_id).await?;
+    let node = transaction.fetch_current_user_node(user_id).await?;
+    transaction.publish(Event::PlayerLeft {
+        node_id: from,
+        user: user.clone(),
+        hidden,
+    });
+    transaction.publish(Event::PlayerEntered {
+        node_id: to,
+        user,
+        hidden,
+    });
+    transaction.commit().await?;
+
+    Ok(Json(NodeResponse { node }))
+}
+
 pub async fn get_node(
     State(game): State<Game>,
     Path(node_id): Path<NodeId>,
@@ -35,3 +76,177 @@ pub async fn get_node(
     transaction.commit().await?;
     Ok(Json(NodeResponse { node }))
 }
+
+pub async fn cast(
+    State(game): State<Game>,
+    Authenticated(user_id): Authenticated,
+    Path(node_id): Path<NodeId>,
+    Json(cast_request): Json<CastRequest>,
+) -> Result<Json<NodeResponse>, Error> {
+    let mut transaction = game.transaction().await?;
+
+    // players can only cast spells on the node they're in.
+    if transaction.fetch_user_node_id(user_id).await? != node_id {
+        return Err(ApiError::NotInNode.into());
+    }
+
+    let node = transaction.fetch_node(node_id).await?;
+    if node.content.atom_text(cast_request.position).is_none() {
+        return Err(ApiError::InvalidAtomPosition.into());
+    }
+
+    // check that the user has the spell before generating anything. it's only used
+    // up once the node exists, so that no rows are locked while we wait for the AI.
+    remove_spell(&mut transaction, user_id, cast_request.spell).await?;
+    transaction.rollback().await?;
+
+    // casting the same spell on the same atom always leads to the same node.
+    let key = ChildKey {
+        parent_id: node_id,
+        fork: Some(Fork {
+            position: cast_request.position,
+            spell: cast_request.spell,
+        }),
+    };
+    let child_id = game.generate_child_node(key, user_id).await?;
+
+    let mut transaction = game.transaction().await?;
+
+    // the spell might be gone by now, in which case the transaction is rolled back.
+    remove_spell(&mut transaction, user_id, cast_request.spell).await?;
+    transaction.publish(Event::InventoryChanged { user_id });
+
+    let node = transaction.fetch_node(child_id).await?;
+    transaction.commit().await?;
+
+    Ok(Json(NodeResponse { node }))
+}
+
+/// Takes one of the spell out of the user's inventory.
+async fn remove_spell(
+    transaction: &mut Transaction<'_>,
+    user_id: UserId,
+    spell: SpellId,
+) -> Result<(), Error> {
+    transaction
+        .remove_from_inventory(user_id, SpellAmount { spell, amount: 1 })
+        .await?
+        .ok_or(ApiError::MissingSpell)?;
+    Ok(())
+}
+
+pub async fn advance(
+    State(game): State<Game>,
+    Authenticated(user_id): Authenticated,
+    Path(node_id): Path<NodeId>,
+) -> Result<Json<NodeResponse>, Error> {
+    // a node has at most one natural child, so we only generate it once.
+    let key = ChildKey {
+        parent_id: node_id,
+        fork: None,
+    };
+    let child_id = game.generate_child_node(key, user_id).await?;
+
+    let mut transaction = game.transaction().await?;
+    let node = transaction.fetch_node(child_id).await?;
+    transaction.commit().await?;
+
+    Ok(Json(NodeResponse { node }))
+}
+
+#[cfg(test)]
+mod tests {
+    use semantica_protocol::node::MoveTarget;
+    use sqlx::PgPool;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::game::spell::get_spell_id_for_name;
+
+    #[sqlx::test(migrations = false)]
+    async fn it_uses_up_cast_spells(pool: PgPool) {
+        let game = Game::new_for_test(pool).await.unwrap();
+        let (user_id, _) = game.create_user_for_test("alice").await.unwrap();
+        let fire = get_spell_id_for_name("Fire");
+
+        let mut transaction = game.transaction().await.unwrap();
+        let node_id = transaction.fetch_user_node_id(user_id).await.unwrap();
+        transaction
+            .add_to_inventory(
+                user_id,
+                SpellAmount {
+                    spell: fire,
+                    amount: 1,
+                },
+            )
+            .await
+            .unwrap();
+        transaction.commit().await.unwrap();
+
+        let cast_on = |node_id, position| {
+            cast(
+                State(game.clone()),
+                Authenticated(user_id),
+                Path(node_id),
+                Json(CastRequest {
+                    position,
+                    spell: fire,
+                }),
+            )
+        };
+
+        let result = cast_on(NodeId(Uuid::new_v4()), 0).await;
+        assert!(matches!(result, Err(Error::Api(ApiError::NotInNode))));
+        let result = cast_on(node_id, usize::MAX).await;
+        assert!(matches!(
+            result,
+            Err(Error::Api(ApiError::InvalidAtomPosition))
+        ));
+
+        let Json(response) = cast_on(node_id, 0).await.unwrap();
+        assert!(response.node.parent.is_some());
+
+        // the only fire spell was used up.
+        let result = cast_on(node_id, 0).await;
+        assert!(matches!(result, Err(Error::Api(ApiError::MissingSpell))));
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_only_moves_to_adjacent_nodes(pool: PgPool) {
+        let game = Game::new_for_test(pool).await.unwrap();
+        let (user_id, _) = game.create_user_for_test("alice").await.unwrap();
+
+        let move_to_target = |target| {
+            move_to(
+                State(game.clone()),
+                Authenticated(user_id),
+                Json(MoveRequest { target }),
+            )
+        };
+
+        // the user starts at the root node, which has no parent, and nobody advanced
+        // the story yet.
+        for target in [
+            MoveTarget::Parent,
+            MoveTarget::NaturalChild,
+            MoveTarget::ForkChild(NodeId(Uuid::new_v4())),
+        ] {
+            let result = move_to_target(target).await;
+            assert!(matches!(result, Err(Error::Api(ApiError::NotAdjacent))));
+        }
+
+        let mut transaction = game.transaction().await.unwrap();
+        let root_node_id = transaction.fetch_user_node_id(user_id).await.unwrap();
+        transaction.commit().await.unwrap();
+
+        let Json(response) = advance(
+            State(game.clone()),
+            Authenticated(user_id),
+            Path(root_node_id),
+        )
+        .await
+        .unwrap();
+        let Json(moved) = move_to_target(MoveTarget::NaturalChild).await.unwrap();
+        assert_eq!(moved.node.node_id, response.node.node_id);
+    }
+}
@@ -0,0 +1,100 @@
+use axum::{
+    extract::{
+        Path,
+        State,
+    },
+    Json,
+};
+use semantica_protocol::{
+    error::ApiError,
+    event::Event,
+    node::{
+        NodeId,
+        PresenceResponse,
+    },
+    user::VisibilityRequest,
+};
+
+use super::auth::Authenticated;
+use crate::{
+    error::Error,
+    game::Game,
+};
+
+pub async fn get_presence(
+    State(game): State<Game>,
+    Authenticated(user_id): Authenticated,
+    Path(node_id): Path<NodeId>,
+) -> Result<Json<PresenceResponse>, Error> {
+    let mut transaction = game.transaction().await?;
+
+    // players can only see who's around in the node they're in.
+    if transaction.fetch_user_node_id(user_id).await? != node_id {
+        return Err(ApiError::NotInNode.into());
+    }
+
+    let users = transaction.fetch_node_presence(node_id, user_id).await?;
+    transaction.commit().await?;
+    Ok(Json(PresenceResponse { users }))
+}
+
+pub async fn set_visibility(
+    State(game): State<Game>,
+    Authenticated(user_id): Authenticated,
+    Json(visibility_request): Json<VisibilityRequest>,
+) -> Result<(), Error> {
+    let visible = visibility_request.visible;
+
+    let mut transaction = game.transaction().await?;
+    if transaction.set_user_visible(user_id, visible).await? {
+        // to the other players in the node, this looks like the user is arriving or
+        // leaving.
+        let node_id = transaction.fetch_user_node_id(user_id).await?;
+        let user = transaction.fetch_user_link(user_id).await?;
+        let event = if visible {
+            Event::PlayerEntered {
+                node_id,
+                user,
+                hidden: false,
+            }
+        }
+        else {
+            Event::PlayerLeft {
+                node_id,
+                user,
+                hidden: false,
+            }
+        };
+        transaction.publish(event);
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::PgPool;
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[sqlx::test(migrations = false)]
+    async fn it_only_shows_presence_in_the_users_node(pool: PgPool) {
+        let game = Game::new_for_test(pool).await.unwrap();
+        let (user_id, _) = game.create_user_for_test("alice").await.unwrap();
+
+        let mut transaction = game.transaction().await.unwrap();
+        let node_id = transaction.fetch_user_node_id(user_id).await.unwrap();
+        transaction.commit().await.unwrap();
+
+        let Json(presence) =
+            get_presence(State(game.clone()), Authenticated(user_id), Path(node_id))
+                .await
+                .unwrap();
+        assert!(presence.users.iter().any(|user| user.user_id == user_id));
+
+        let other_node_id = NodeId(Uuid::new_v4());
+        let result = get_presence(State(game), Authenticated(user_id), Path(other_node_id)).await;
+        assert!(matches!(result, Err(Error::Api(ApiError::NotInNode))));
+    }
+}
@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use axum::{
     http::StatusCode,
     response::IntoResponse,
@@ -27,6 +29,15 @@ pub enum Error {
     #[error("hf-api")]
     HfApi(#[from] hf_textgen::Error),
 
+    #[error("reqwest")]
+    Reqwest(#[from] reqwest::Error),
+
+    #[error("invalid config value for {key}: {value}")]
+    Config { key: &'static str, value: String },
+
+    #[error("invalid crafting result")]
+    InvalidCraftingResult(#[from] crate::game::ai::InvalidCraftingResult),
+
     #[error("api")]
     Api(#[from] ApiError),
 
@@ -41,21 +52,42 @@ pub enum Error {
 
     #[error("io")]
     Io(#[from] std::io::Error),
+
+    #[error("task")]
+    Join(#[from] tokio::task::JoinError),
+
+    /// An error that is shared by everyone waiting for the same computation.
+    #[error("shared")]
+    Shared(#[source] Arc<Error>),
+}
+
+impl Error {
+    /// The error that should be returned to API clients, if this error is
+    /// meant for them.
+    fn api_error(&self) -> Option<ApiError> {
+        match self {
+            Error::Api(error) => Some(error.clone()),
+            Error::Sqlx(sqlx::Error::RowNotFound) => Some(ApiError::NotFound),
+            Error::InvalidCraftingResult(_) => Some(ApiError::GenerationFailed),
+            Error::Shared(error) => error.api_error(),
+            _ => None,
+        }
+    }
 }
 
 impl From<Error> for ApiError {
     fn from(error: Error) -> Self {
-        match error {
-            Error::Api(error) => error,
-            _ => {
-                let mut error: &dyn std::error::Error = &error;
-                tracing::error!("returning internal server error: {error}");
-                while let Some(source) = error.source() {
-                    tracing::error!(" - {source}");
-                    error = source;
-                }
-                ApiError::Internal
+        if let Some(api_error) = error.api_error() {
+            api_error
+        }
+        else {
+            let mut error: &dyn std::error::Error = &error;
+            tracing::error!("returning internal server error: {error}");
+            while let Some(source) = error.source() {
+                tracing::error!(" - {source}");
+                error = source;
             }
+            ApiError::Internal
         }
     }
 }
@@ -68,10 +100,10 @@ impl From<argon2::password_hash::Error> for Error {
 
 impl AsStatusCode for Error {
     fn as_status_code(&self) -> StatusCode {
-        match self {
-            Error::Api(error) => error.as_status_code(),
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
-        }
+        self.api_error()
+            .map_or(StatusCode::INTERNAL_SERVER_ERROR, |error| {
+                error.as_status_code()
+            })
     }
 }
 
@@ -0,0 +1,314 @@
+use serde::Deserialize;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Maximum length of a spell name in characters.
+pub const MAX_NAME_LENGTH: usize = 32;
+
+/// Maximum length of a spell description in characters.
+pub const MAX_DESCRIPTION_LENGTH: usize = 256;
+
+#[derive(Clone, Debug)]
+pub struct CraftingResult {
+    pub name: String,
+    pub emoji: String,
+    pub description: String,
+}
+
+/// The reason why a response from the crafting model was rejected.
+///
+/// The message is shown to the model when we ask it to correct its response.
+#[derive(Debug, thiserror::Error)]
+pub enum InvalidCraftingResult {
+    #[error("the response doesn't contain a JSON object")]
+    NoJson,
+
+    #[error("the JSON object is invalid: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("the name is empty")]
+    EmptyName,
+
+    #[error("the name is longer than {MAX_NAME_LENGTH} characters")]
+    NameTooLong,
+
+    #[error("the emoji must be exactly one emoji, but was {0:?}")]
+    InvalidEmoji(String),
+
+    #[error("the description is empty")]
+    EmptyDescription,
+
+    #[error("the description is longer than {MAX_DESCRIPTION_LENGTH} characters")]
+    DescriptionTooLong,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawCraftingResult {
+    name: String,
+    emoji: String,
+    description: String,
+}
+
+impl CraftingResult {
+    /// Parses and validates the response of the crafting model.
+    ///
+    /// Any text around the first JSON object is ignored.
+    pub fn parse(response: &str) -> Result<Self, InvalidCraftingResult> {
+        let start = response.find('{').ok_or(InvalidCraftingResult::NoJson)?;
+        let raw: RawCraftingResult = serde_json::Deserializer::from_str(&response[start..])
+            .into_iter()
+            .next()
+            .ok_or(InvalidCraftingResult::NoJson)??;
+
+        let name = normalize_name(&raw.name);
+        if name.is_empty() {
+            return Err(InvalidCraftingResult::EmptyName);
+        }
+        if name.chars().count() > MAX_NAME_LENGTH {
+            return Err(InvalidCraftingResult::NameTooLong);
+        }
+
+        let emoji = raw.emoji.trim();
+        if emoji.graphemes(true).count() != 1 || !is_emoji(emoji) {
+            return Err(InvalidCraftingResult::InvalidEmoji(raw.emoji));
+        }
+
+        let description = raw.description.trim();
+        if description.is_empty() {
+            return Err(InvalidCraftingResult::EmptyDescription);
+        }
+        if description.chars().count() > MAX_DESCRIPTION_LENGTH {
+            return Err(InvalidCraftingResult::DescriptionTooLong);
+        }
+
+        Ok(Self {
+            name,
+            emoji: emoji.to_owned(),
+            description: description.to_owned(),
+        })
+    }
+}
+
+/// Words that are kept in lower case, unless they're the first or last word of
+/// a name.
+const MINOR_WORDS: &[&str] = &[
+    "a", "an", "and", "as", "at", "but", "by", "for", "from", "in", "into", "nor", "of", "on",
+    "or", "the", "to", "with",
+];
+
+/// Collapses whitespace and converts the name to title case.
+///
+/// Minor words like "of" are lower case unless they're the first or last word.
+/// Parts of hyphenated words are capitalized individually, and words in all
+/// caps are kept as they are, since they're probably acronyms.
+fn normalize_name(name: &str) -> String {
+    let words = name.split_whitespace().collect::<Vec<_>>();
+    let last = words.len().saturating_sub(1);
+
+    words
+        .iter()
+        .enumerate()
+        .map(|(i, word)| {
+            let is_acronym = word.chars().filter(|c| c.is_alphabetic()).count() > 1
+                && !word.chars().any(char::is_lowercase);
+            if is_acronym {
+                return word.to_string();
+            }
+
+            let word = word.to_lowercase();
+            if i != 0 && i != last && MINOR_WORDS.contains(&word.as_str()) {
+                word
+            }
+            else {
+                word.split('-')
+                    .map(capitalize)
+                    .collect::<Vec<_>>()
+                    .join("-")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    chars
+        .next()
+        .into_iter()
+        .flat_map(char::to_uppercase)
+        .chain(chars)
+        .collect()
+}
+
+/// Whether a grapheme is an emoji.
+///
+/// The grapheme has to start with an emoji: either a character from the blocks
+/// that contain pictographic emoji, which may be followed by modifiers and
+/// joined emoji, a keycap like `#️⃣`, or one of the symbols (like `©`) that the
+/// variation selector turns into emoji. Plain ASCII and letters are not emoji,
+/// even with a variation selector.
+fn is_emoji(grapheme: &str) -> bool {
+    let mut chars = grapheme.chars();
+    let Some(base) = chars.next()
+    else {
+        return false;
+    };
+    let rest = chars.as_str();
+
+    match base {
+        '\u{1f000}'..='\u{1faff}'
+        | '\u{2600}'..='\u{27bf}'
+        | '\u{2300}'..='\u{23ff}'
+        | '\u{2b00}'..='\u{2bff}'
+        | '\u{3030}'
+        | '\u{303d}'
+        | '\u{3297}'
+        | '\u{3299}' => true,
+        '0'..='9' | '#' | '*' => matches!(rest, "\u{20e3}" | "\u{fe0f}\u{20e3}"),
+        '\u{a9}'
+        | '\u{ae}'
+        | '\u{203c}'
+        | '\u{2049}'
+        | '\u{2122}'
+        | '\u{2139}'
+        | '\u{2194}'..='\u{2199}'
+        | '\u{21a9}'
+        | '\u{21aa}'
+        | '\u{25aa}'
+        | '\u{25ab}'
+        | '\u{25b6}'
+        | '\u{25c0}'
+        | '\u{25fb}'..='\u{25fe}'
+        | '\u{2934}'
+        | '\u{2935}' => rest.starts_with('\u{fe0f}'),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(name: &str, emoji: &str, description: &str) -> String {
+        serde_json::json!({
+            "name": name,
+            "emoji": emoji,
+            "description": description,
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn it_title_cases_names() {
+        assert_eq!(normalize_name("mud"), "Mud");
+        assert_eq!(normalize_name("  hot   sPRING "), "Hot Spring");
+        assert_eq!(normalize_name("pot of gold"), "Pot of Gold");
+        assert_eq!(normalize_name("the end of the line"), "The End of the Line");
+        assert_eq!(normalize_name("what it's made of"), "What It's Made Of");
+        assert_eq!(
+            normalize_name("fire-breathing dragon"),
+            "Fire-Breathing Dragon"
+        );
+        assert_eq!(normalize_name("philosopher's stone"), "Philosopher's Stone");
+        assert_eq!(normalize_name("DNA strand"), "DNA Strand");
+        assert_eq!(normalize_name("éclair"), "Éclair");
+        assert_eq!(normalize_name("a"), "A");
+        assert_eq!(normalize_name(""), "");
+    }
+
+    #[test]
+    fn it_accepts_emoji() {
+        for emoji in [
+            "💩",
+            "❤",
+            "❤️",
+            "⭐",
+            "👨‍👩‍👧",
+            "🇩🇪",
+            "#️⃣",
+            "7\u{20e3}",
+            "©️",
+            "👍🏽",
+        ] {
+            assert!(is_emoji(emoji), "{emoji:?}");
+            assert!(
+                CraftingResult::parse(&response("Mud", emoji, "Wet earth.")).is_ok(),
+                "{emoji:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn it_rejects_non_emoji() {
+        for emoji in [
+            "",
+            "#",
+            "!",
+            "a",
+            "7",
+            "é",
+            "→",
+            "©",
+            "💩💩",
+            "mud",
+            "a\u{fe0f}",
+            "7\u{fe0f}",
+            "a\u{20e3}",
+        ] {
+            assert!(
+                matches!(
+                    CraftingResult::parse(&response("Mud", emoji, "Wet earth.")),
+                    Err(InvalidCraftingResult::InvalidEmoji(_))
+                ),
+                "{emoji:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn it_parses_json_surrounded_by_text() {
+        let result = CraftingResult::parse(&format!(
+            "Sure! {} Hope that helps {{",
+            response(" hot spring ", " ♨️ ", " Warm water. ")
+        ))
+        .unwrap();
+        assert_eq!(result.name, "Hot Spring");
+        assert_eq!(result.emoji, "♨️");
+        assert_eq!(result.description, "Warm water.");
+    }
+
+    #[test]
+    fn it_rejects_invalid_responses() {
+        assert!(matches!(
+            CraftingResult::parse("no json here"),
+            Err(InvalidCraftingResult::NoJson)
+        ));
+        assert!(matches!(
+            CraftingResult::parse(r#"{"name": "Mud"}"#),
+            Err(InvalidCraftingResult::Json(_))
+        ));
+        assert!(matches!(
+            CraftingResult::parse(&response("  ", "💩", "Wet earth.")),
+            Err(InvalidCraftingResult::EmptyName)
+        ));
+        assert!(matches!(
+            CraftingResult::parse(&response(
+                &"a".repeat(MAX_NAME_LENGTH + 1),
+                "💩",
+                "Wet earth."
+            )),
+            Err(InvalidCraftingResult::NameTooLong)
+        ));
+        assert!(matches!(
+            CraftingResult::parse(&response("Mud", "💩", " ")),
+            Err(InvalidCraftingResult::EmptyDescription)
+        ));
+        assert!(matches!(
+            CraftingResult::parse(&response(
+                "Mud",
+                "💩",
+                &"a".repeat(MAX_DESCRIPTION_LENGTH + 1)
+            )),
+            Err(InvalidCraftingResult::DescriptionTooLong)
+        ));
+    }
+}
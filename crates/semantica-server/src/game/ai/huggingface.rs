@@ -0,0 +1,39 @@
+use hf_textgen::{
+    Api,
+    TextGeneration,
+};
+
+use super::TextGenerator;
+use crate::error::Error;
+
+pub fn api(hf_token: Option<String>) -> Api {
+    let mut builder = Api::builder();
+    if let Some(hf_token) = hf_token {
+        builder = builder.with_hf_token(hf_token)
+    }
+    else {
+        tracing::warn!("HF_TOKEN not set");
+    }
+    builder.build()
+}
+
+/// Text generation with a model hosted by the Hugging Face inference API.
+#[derive(Clone, Debug)]
+pub struct HuggingFace {
+    model: TextGeneration,
+}
+
+impl HuggingFace {
+    pub fn new(api: &Api, model: &str) -> Self {
+        Self {
+            model: api.text_generation(model),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl TextGenerator for HuggingFace {
+    async fn generate(&self, prompt: &str) -> Result<String, Error> {
+        Ok(self.model.generate(prompt).await?)
+    }
+}
@@ -0,0 +1,212 @@
+mod crafting;
+mod huggingface;
+mod openai;
+mod scripted;
+
+use std::{
+    fmt::Debug,
+    sync::Arc,
+};
+
+use askama::Template;
+use semantica_protocol::{
+    node::Content,
+    spell::Spell,
+    user::UserId,
+    Links,
+};
+use shuttle_secrets::SecretStore;
+
+pub use self::{
+    crafting::{
+        CraftingResult,
+        InvalidCraftingResult,
+    },
+    huggingface::HuggingFace,
+    openai::OpenAi,
+    scripted::Scripted,
+};
+use crate::error::Error;
+
+/// How often the crafting model is asked to correct an invalid response.
+const MAX_CRAFTING_RETRIES: usize = 2;
+
+const DEFAULT_MODEL: &str = "NousResearch/Nous-Hermes-2-Mixtral-8x7B-DPO";
+
+/// A backend that completes prompts.
+#[async_trait::async_trait]
+pub trait TextGenerator: Debug + Send + Sync {
+    async fn generate(&self, prompt: &str) -> Result<String, Error>;
+}
+
+#[derive(Clone, Debug)]
+pub struct Ai {
+    crafting_model: Arc<dyn TextGenerator>,
+    world_model: Arc<dyn TextGenerator>,
+}
+
+impl Ai {
+    /// Creates the text generators for the backend selected by `AI_BACKEND`.
+    ///
+    /// Supported backends are `huggingface` (the default), `openai` for
+    /// OpenAI-compatible servers, and `scripted` for offline development.
+    pub fn new(secrets: &SecretStore) -> Result<Self, Error> {
+        let backend = secrets
+            .get("AI_BACKEND")
+            .unwrap_or_else(|| "huggingface".to_owned());
+        let crafting_model = secrets
+            .get("AI_CRAFTING_MODEL")
+            .unwrap_or_else(|| DEFAULT_MODEL.to_owned());
+        let world_model = secrets
+            .get("AI_WORLD_MODEL")
+            .unwrap_or_else(|| DEFAULT_MODEL.to_owned());
+
+        let (crafting_model, world_model): (Arc<dyn TextGenerator>, Arc<dyn TextGenerator>) =
+            match backend.as_str() {
+                "huggingface" => {
+                    let api = huggingface::api(secrets.get("HF_TOKEN"));
+                    (
+                        Arc::new(HuggingFace::new(&api, &crafting_model)),
+                        Arc::new(HuggingFace::new(&api, &world_model)),
+                    )
+                }
+                "openai" => {
+                    let client = reqwest::Client::new();
+                    let base_url = secrets
+                        .get("OPENAI_BASE_URL")
+                        .unwrap_or_else(|| openai::DEFAULT_BASE_URL.to_owned());
+                    let api_key = secrets.get("OPENAI_API_KEY");
+                    (
+                        Arc::new(OpenAi::new(
+                            client.clone(),
+                            base_url.clone(),
+                            api_key.clone(),
+                            crafting_model,
+                        )),
+                        Arc::new(OpenAi::new(client, base_url, api_key, world_model)),
+                    )
+                }
+                "scripted" => (Arc::new(Scripted::crafting()), Arc::new(Scripted::world())),
+                _ => {
+                    return Err(Error::Config {
+                        key: "AI_BACKEND",
+                        value: backend,
+                    })
+                }
+            };
+
+        tracing::info!(backend, "text generation backend");
+
+        Ok(Self {
+            crafting_model,
+            world_model,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn scripted() -> Self {
+        Self {
+            crafting_model: Arc::new(Scripted::crafting()),
+            world_model: Arc::new(Scripted::world()),
+        }
+    }
+
+    pub async fn craft(&self, ingredients: &[&str]) -> Result<CraftingResult, Error> {
+        #[derive(Debug, Template)]
+        #[template(path = "crafting_prompt.txt")]
+        struct CraftingPrompt<'a> {
+            ingredients: &'a [&'a str],
+        }
+
+        #[derive(Debug, Template)]
+        #[template(path = "crafting_repair_prompt.txt")]
+        struct CraftingRepairPrompt<'a> {
+            prompt: &'a str,
+            response: &'a str,
+            problem: &'a str,
+        }
+
+        let mut prompt = CraftingPrompt { ingredients }.render()?;
+        let mut retries = 0;
+
+        loop {
+            let response = self.crafting_model.generate(&prompt).await?;
+
+            match CraftingResult::parse(&response) {
+                Ok(product) => return Ok(product),
+                Err(error) if retries < MAX_CRAFTING_RETRIES => {
+                    tracing::debug!(%error, response, "invalid crafting result, retrying");
+                    retries += 1;
+                    prompt = CraftingRepairPrompt {
+                        prompt: &prompt,
+                        response: &response,
+                        problem: &error.to_string(),
+                    }
+                    .render()?;
+                }
+                Err(error) => {
+                    tracing::warn!(%error, response, "invalid crafting result, giving up");
+                    return Err(error.into());
+                }
+            }
+        }
+    }
+
+    /// Generates the text of the natural continuation of a node.
+    pub async fn advance(&self, content: &Content) -> Result<String, Error> {
+        #[derive(Debug, Template)]
+        #[template(path = "advance_prompt.txt")]
+        struct AdvancePrompt<'a> {
+            paragraphs: Vec<&'a str>,
+        }
+
+        let prompt = AdvancePrompt {
+            paragraphs: content
+                .paragraphs
+                .iter()
+                .map(|paragraph| paragraph.text.as_str())
+                .collect(),
+        }
+        .render()?;
+
+        let response = self.world_model.generate(&prompt).await?;
+
+        Ok(response.trim().to_owned())
+    }
+
+    /// Generates the text of a new node that is forked from a node by casting
+    /// a spell on one of its atoms.
+    pub async fn fork<CreatedBy: Links<UserId>>(
+        &self,
+        content: &Content,
+        atom: &str,
+        spell: &Spell<CreatedBy>,
+    ) -> Result<String, Error> {
+        #[derive(Debug, Template)]
+        #[template(path = "fork_prompt.txt")]
+        struct ForkPrompt<'a> {
+            paragraphs: Vec<&'a str>,
+            atom: &'a str,
+            spell_name: &'a str,
+            spell_emoji: &'a str,
+            spell_description: &'a str,
+        }
+
+        let prompt = ForkPrompt {
+            paragraphs: content
+                .paragraphs
+                .iter()
+                .map(|paragraph| paragraph.text.as_str())
+                .collect(),
+            atom,
+            spell_name: &spell.name,
+            spell_emoji: &spell.emoji,
+            spell_description: &spell.description,
+        }
+        .render()?;
+
+        let response = self.world_model.generate(&prompt).await?;
+
+        Ok(response.trim().to_owned())
+    }
+}
@@ -0,0 +1,85 @@
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use super::TextGenerator;
+use crate::error::Error;
+
+pub const DEFAULT_BASE_URL: &str = "http://localhost:8080/v1";
+
+const MAX_TOKENS: usize = 1024;
+
+/// Our prompts are in ChatML, so the completion ends with the end of the
+/// assistant's message.
+const STOP: &[&str] = &["<|im_end|>"];
+
+/// Text generation with a server that implements the OpenAI completions API,
+/// e.g. llama.cpp or vLLM.
+#[derive(Clone, Debug)]
+pub struct OpenAi {
+    client: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+    model: String,
+}
+
+impl OpenAi {
+    pub fn new(
+        client: reqwest::Client,
+        base_url: String,
+        api_key: Option<String>,
+        model: String,
+    ) -> Self {
+        Self {
+            client,
+            base_url,
+            api_key,
+            model,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct CompletionRequest<'a> {
+    model: &'a str,
+    prompt: &'a str,
+    max_tokens: usize,
+    stop: &'a [&'a str],
+}
+
+#[derive(Debug, Deserialize)]
+struct CompletionResponse {
+    choices: Vec<CompletionChoice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CompletionChoice {
+    text: String,
+}
+
+#[async_trait::async_trait]
+impl TextGenerator for OpenAi {
+    async fn generate(&self, prompt: &str) -> Result<String, Error> {
+        let url = format!("{}/completions", self.base_url.trim_end_matches('/'));
+
+        let mut request = self.client.post(url).json(&CompletionRequest {
+            model: &self.model,
+            prompt,
+            max_tokens: MAX_TOKENS,
+            stop: STOP,
+        });
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+
+        let response: CompletionResponse = request.send().await?.error_for_status()?.json().await?;
+
+        Ok(response
+            .choices
+            .into_iter()
+            .next()
+            .map(|choice| choice.text)
+            .unwrap_or_default())
+    }
+}
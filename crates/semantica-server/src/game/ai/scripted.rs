@@ -0,0 +1,60 @@
+use murmur3::Murmur3x64x128;
+
+use super::TextGenerator;
+use crate::error::Error;
+
+const SEED: u32 = 3;
+
+const CRAFTING_RESPONSES: &[&str] = &[
+    r#"{"name": "Steam", "emoji": "💨", "description": "A hot cloud that rises from boiling water."}"#,
+    r#"{"name": "Mud", "emoji": "🟤", "description": "Wet earth that sticks to everything it touches."}"#,
+    r#"{"name": "Lava", "emoji": "🌋", "description": "Molten rock that melts whatever it flows over."}"#,
+    r#"{"name": "Storm", "emoji": "⛈️", "description": "Wind and rain that batter everything in their path."}"#,
+    r#"{"name": "Plant", "emoji": "🌱", "description": "A small sprout that grows towards the light."}"#,
+    r#"{"name": "Glass", "emoji": "🪟", "description": "A clear, brittle pane made from melted sand."}"#,
+    r#"{"name": "Mist", "emoji": "🌫️", "description": "A thin fog that hides what lies behind it."}"#,
+    r#"{"name": "Spark", "emoji": "✨", "description": "A tiny flash of light that can start a fire."}"#,
+];
+
+const WORLD_RESPONSES: &[&str] = &[
+    "The path winds on between old stone walls. Somewhere ahead, a bell rings once and falls silent.",
+    "A cold wind sweeps across the clearing. The grass bends low, and for a moment everything is still.",
+    "The door creaks open onto a dusty hall. Candles flicker in their holders, though no one has lit them.",
+    "Rain begins to fall, soft at first, then heavier. The puddles gather into a small stream that runs downhill.",
+];
+
+/// A deterministic text generator for offline development and tests.
+///
+/// The response is chosen from a fixed list by hashing the prompt, so the same
+/// prompt always produces the same response.
+#[derive(Clone, Debug)]
+pub struct Scripted {
+    responses: &'static [&'static str],
+}
+
+impl Scripted {
+    pub fn new(responses: &'static [&'static str]) -> Self {
+        assert!(!responses.is_empty());
+        Self { responses }
+    }
+
+    /// Responds with crafting results in the JSON format the crafting prompt
+    /// asks for.
+    pub fn crafting() -> Self {
+        Self::new(CRAFTING_RESPONSES)
+    }
+
+    /// Responds with paragraphs of story text.
+    pub fn world() -> Self {
+        Self::new(WORLD_RESPONSES)
+    }
+}
+
+#[async_trait::async_trait]
+impl TextGenerator for Scripted {
+    async fn generate(&self, prompt: &str) -> Result<String, Error> {
+        let hash = murmur3::hash::<Murmur3x64x128, _>(SEED, prompt.as_bytes());
+        let index = (hash % self.responses.len() as u128) as usize;
+        Ok(self.responses[index].to_owned())
+    }
+}
@@ -1,3 +1,5 @@
+use std::net::IpAddr;
+
 use argon2::{
     password_hash::SaltString,
     Argon2,
@@ -5,22 +7,57 @@ use argon2::{
     PasswordHasher,
     PasswordVerifier,
 };
+use chrono::Duration;
 use rand::thread_rng;
 use semantica_protocol::{
     auth::{
+        AccessToken,
         AuthRequest,
         AuthSecret,
+        DeviceId,
+        LoginMethod,
+        Password,
+        Secret,
+        SessionId,
+        SessionInfo,
+        MAX_LOGIN_NAME_LENGTH,
+        MIN_LOGIN_NAME_LENGTH,
     },
+    error::ApiError,
     node::NodeId,
-    user::UserId,
+    user::{
+        UserId,
+        UserLink,
+    },
 };
+use sha2::{
+    Digest,
+    Sha256,
+};
+use tokio::sync::OnceCell;
 
 use super::Transaction;
 use crate::{
     api::auth::create_secret,
     error::Error,
+    utils::convert::FromDb,
 };
 
+/// A successful authentication.
+#[derive(Clone, Copy, Debug)]
+pub struct Login {
+    pub user_id: UserId,
+    pub method: LoginMethod,
+    /// The device secret that was used, if it wasn't the user's main secret.
+    pub device_id: Option<DeviceId>,
+}
+
+/// How many failed password logins for a login name from one client IP are
+/// allowed within the rate limit window.
+const MAX_FAILED_LOGINS_PER_WINDOW: i64 = 5;
+
+const FAILED_LOGINS_WINDOW_MINUTES: i64 = 15;
+
 impl<'a> Transaction<'a> {
     pub async fn insert_user(
         &mut self,
@@ -28,7 +65,7 @@ impl<'a> Transaction<'a> {
         name: &str,
         auth_secret: AuthSecret,
     ) -> Result<(), Error> {
-        let auth_secret_hash = hash_auth_secret(auth_secret).await;
+        let auth_secret_hash = hash_secret(auth_secret.0).await;
 
         // todo
         let start_node: NodeId = sqlx::query_scalar!("SELECT node_id FROM root_nodes LIMIT 1")
@@ -44,8 +81,9 @@ impl<'a> Transaction<'a> {
                 auth_secret,
                 created_at,
                 last_login,
+                last_active,
                 in_node
-            ) VALUES ($1, $2, $3, utc_now(), utc_now(), $4)"#,
+            ) VALUES ($1, $2, $3, utc_now(), utc_now(), utc_now(), $4)"#,
             user_id.0,
             name,
             &auth_secret_hash,
@@ -57,11 +95,372 @@ impl<'a> Transaction<'a> {
         Ok(())
     }
 
+    /// Whether the user is an admin.
+    pub async fn fetch_user_god_mode(&mut self, user_id: UserId) -> Result<bool, Error> {
+        let god_mode =
+            sqlx::query_scalar!("SELECT god_mode FROM users WHERE user_id = $1", user_id.0,)
+                .fetch_one(self.db())
+                .await?;
+        Ok(god_mode)
+    }
+
+    pub async fn fetch_user_link(&mut self, user_id: UserId) -> Result<UserLink, Error> {
+        let row = sqlx::query!("SELECT name FROM users WHERE user_id = $1", user_id.0)
+            .fetch_one(self.db())
+            .await?;
+        Ok(UserLink {
+            user_id,
+            name: row.name,
+        })
+    }
+
+    /// Creates a new access token for a login.
+    ///
+    /// The user agent is only stored, so that the user can tell their sessions
+    /// apart.
+    pub async fn insert_access_token(
+        &mut self,
+        login: Login,
+        user_agent: Option<&str>,
+    ) -> Result<AccessToken, Error> {
+        let access_token = create_access_token();
+        let now = self.now();
+
+        sqlx::query!(
+            r#"
+            INSERT INTO access_tokens (
+                token_hash,
+                user_id,
+                device_id,
+                created_at,
+                expires_at,
+                user_agent
+            ) VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            hash_token(&access_token.0 .0),
+            login.user_id.0,
+            login.device_id.map(|device_id| device_id.0),
+            now.naive_utc(),
+            (now + self.game.access_token_lifetime()).naive_utc(),
+            user_agent,
+        )
+        .execute(self.db())
+        .await?;
+
+        Ok(access_token)
+    }
+
+    /// Returns the user an access token belongs to, if it is valid.
+    ///
+    /// Using an access token keeps it valid for longer, like a session.
+    pub async fn authenticate_access_token(
+        &mut self,
+        access_token: &AccessToken,
+    ) -> Result<Option<UserId>, Error> {
+        let now = self.now();
+        let user_id = sqlx::query_scalar!(
+            r#"
+            UPDATE access_tokens SET expires_at = $3
+            WHERE token_hash = $1 AND expires_at > $2
+            RETURNING user_id
+            "#,
+            hash_token(&access_token.0 .0),
+            now.naive_utc(),
+            (now + self.game.access_token_lifetime()).naive_utc(),
+        )
+        .fetch_optional(self.db())
+        .await?;
+        Ok(user_id
+            .map(Into::into)
+            .filter(|user_id| !self.is_disabled_test_user(*user_id)))
+    }
+
+    /// Deletes access tokens that have expired.
+    pub async fn delete_expired_access_tokens(&mut self) -> Result<u64, Error> {
+        let result = sqlx::query!(
+            "DELETE FROM access_tokens WHERE expires_at <= $1",
+            self.now().naive_utc(),
+        )
+        .execute(self.db())
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    pub async fn delete_access_token(&mut self, access_token: &AccessToken) -> Result<(), Error> {
+        sqlx::query!(
+            "DELETE FROM access_tokens WHERE token_hash = $1",
+            hash_token(&access_token.0 .0),
+        )
+        .execute(self.db())
+        .await?;
+        Ok(())
+    }
+
+    /// Returns the user's sessions, i.e. their valid access tokens.
+    ///
+    /// If the current access token is given, its session is marked as current.
+    pub async fn fetch_sessions(
+        &mut self,
+        user_id: UserId,
+        current: Option<&AccessToken>,
+    ) -> Result<Vec<SessionInfo>, Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT
+                session_id,
+                created_at,
+                expires_at,
+                user_agent,
+                device_id,
+                COALESCE(token_hash = $3, FALSE) AS "current!"
+            FROM access_tokens
+            WHERE user_id = $1 AND expires_at > $2
+            ORDER BY created_at DESC
+            "#,
+            user_id.0,
+            self.now().naive_utc(),
+            current.map(|access_token| hash_token(&access_token.0 .0)),
+        )
+        .fetch_all(self.db())
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(SessionInfo {
+                    session_id: SessionId(row.session_id),
+                    created_at: row.created_at.from_db()?,
+                    expires_at: row.expires_at.from_db()?,
+                    user_agent: row.user_agent,
+                    device_id: row.device_id.map(DeviceId),
+                    current: row.current,
+                })
+            })
+            .collect()
+    }
+
+    /// Revokes one of the user's sessions.
+    ///
+    /// Returns whether the session existed.
+    pub async fn delete_session(
+        &mut self,
+        user_id: UserId,
+        session_id: SessionId,
+    ) -> Result<bool, Error> {
+        let result = sqlx::query!(
+            "DELETE FROM access_tokens WHERE session_id = $1 AND user_id = $2",
+            session_id.0,
+            user_id.0,
+        )
+        .execute(self.db())
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Revokes all of the user's sessions, i.e. logs them out everywhere.
+    pub async fn delete_sessions(&mut self, user_id: UserId) -> Result<(), Error> {
+        sqlx::query!("DELETE FROM access_tokens WHERE user_id = $1", user_id.0)
+            .execute(self.db())
+            .await?;
+        Ok(())
+    }
+
+    /// Issues a new auth secret for the user.
+    ///
+    /// This logs the user out everywhere, since all access tokens are deleted.
+    /// Device secrets and the password are revoked too, since whoever had the
+    /// old secret could have created devices or set a password with it.
+    pub async fn rotate_auth_secret(&mut self, user_id: UserId) -> Result<AuthSecret, Error> {
+        let auth_secret = create_auth_secret();
+        let auth_secret_hash = hash_secret(auth_secret.0.clone()).await;
+
+        sqlx::query!(
+            "UPDATE users SET auth_secret = $1 WHERE user_id = $2",
+            &auth_secret_hash,
+            user_id.0,
+        )
+        .execute(self.db())
+        .await?;
+
+        self.delete_sessions(user_id).await?;
+        self.delete_devices(user_id).await?;
+        self.delete_password(user_id).await?;
+
+        Ok(auth_secret)
+    }
+
+    /// Sets the user's login name and password.
+    ///
+    /// The login name must already be canonicalized with
+    /// [`canonicalize_login_name`].
+    pub async fn set_password(
+        &mut self,
+        user_id: UserId,
+        login_name: &str,
+        password: Password,
+    ) -> Result<(), Error> {
+        let password_hash = hash_secret(password.0).await;
+
+        sqlx::query!(
+            "UPDATE users SET login_name = $1, password_hash = $2 WHERE user_id = $3",
+            login_name,
+            &password_hash,
+            user_id.0,
+        )
+        .execute(self.db())
+        .await
+        .map_err(|error| {
+            match &error {
+                sqlx::Error::Database(database_error) if database_error.is_unique_violation() => {
+                    ApiError::LoginNameTaken.into()
+                }
+                _ => Error::from(error),
+            }
+        })?;
+
+        Ok(())
+    }
+
+    /// Removes the user's login name and password.
+    pub async fn delete_password(&mut self, user_id: UserId) -> Result<(), Error> {
+        sqlx::query!(
+            "UPDATE users SET login_name = NULL, password_hash = NULL WHERE user_id = $1",
+            user_id.0,
+        )
+        .execute(self.db())
+        .await?;
+        Ok(())
+    }
+
+    /// Fails with [`ApiError::RateLimited`] if there were too many failed
+    /// logins for the login name from the client IP recently.
+    ///
+    /// This is per client IP, so that others can't lock the user out.
+    async fn check_failed_logins(
+        &mut self,
+        login_name: &str,
+        client_ip: IpAddr,
+    ) -> Result<(), Error> {
+        let since = self.now() - Duration::minutes(FAILED_LOGINS_WINDOW_MINUTES);
+
+        let count = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) AS "count!"
+            FROM failed_logins
+            WHERE login_name = $1 AND client_ip = $2 AND attempted_at > $3
+            "#,
+            login_name,
+            client_ip.to_string(),
+            since.naive_utc(),
+        )
+        .fetch_one(self.db())
+        .await?;
+
+        if count >= MAX_FAILED_LOGINS_PER_WINDOW {
+            Err(ApiError::RateLimited.into())
+        }
+        else {
+            Ok(())
+        }
+    }
+
+    /// Deletes failed logins that are outside of the rate limit window.
+    pub async fn delete_old_failed_logins(&mut self) -> Result<u64, Error> {
+        let since = self.now() - Duration::minutes(FAILED_LOGINS_WINDOW_MINUTES);
+        let result = sqlx::query!(
+            "DELETE FROM failed_logins WHERE attempted_at <= $1",
+            since.naive_utc(),
+        )
+        .execute(self.db())
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn authenticate_user_with_password(
+        &mut self,
+        login_name: String,
+        password: Password,
+        client_ip: IpAddr,
+    ) -> Result<Option<Login>, Error> {
+        // invalid login names can't belong to anyone, but we still take as long
+        // as for an unknown login name.
+        let Some(login_name) = canonicalize_login_name(&login_name)
+        else {
+            verify_secret(dummy_password_hash().await, password.0).await;
+            return Ok(None);
+        };
+
+        // this locks the user's row, so that concurrent attempts are counted
+        // correctly.
+        let row = sqlx::query!(
+            "SELECT user_id, password_hash FROM users WHERE login_name = $1 FOR UPDATE",
+            &login_name,
+        )
+        .fetch_optional(self.db())
+        .await?;
+
+        self.check_failed_logins(&login_name, client_ip).await?;
+
+        let (user_id, password_hash) = match row {
+            Some(row) => (Some(row.user_id), row.password_hash),
+            None => {
+                tracing::debug!("login name not found");
+                (None, None)
+            }
+        };
+
+        // without a password hash we still verify against a dummy hash, so that
+        // unknown login names can't be told apart by how long this takes.
+        let password_hash = match password_hash {
+            Some(password_hash) => password_hash,
+            None => dummy_password_hash().await,
+        };
+        let password_ok = verify_secret(password_hash, password.0).await;
+        tracing::debug!(?password_ok);
+
+        let login = user_id.filter(|_| password_ok).map(|user_id| {
+            Login {
+                user_id: user_id.into(),
+                method: LoginMethod::Password,
+                device_id: None,
+            }
+        });
+
+        if login.is_none() {
+            sqlx::query!(
+                r#"
+                INSERT INTO failed_logins (
+                    login_name,
+                    client_ip,
+                    attempted_at
+                ) VALUES ($1, $2, $3)
+                "#,
+                &login_name,
+                client_ip.to_string(),
+                self.now().naive_utc(),
+            )
+            .execute(self.db())
+            .await?;
+        }
+
+        Ok(login)
+    }
+
     async fn authenticate_user_with_secret(
         &mut self,
         user_id: UserId,
         auth_secret: AuthSecret,
-    ) -> Result<bool, Error> {
+    ) -> Result<Option<Login>, Error> {
+        if let Some(device_id) = self
+            .authenticate_device_secret(user_id, &auth_secret)
+            .await?
+        {
+            return Ok(Some(Login {
+                user_id,
+                method: LoginMethod::Device,
+                device_id: Some(device_id),
+            }));
+        }
+
         let Some(row) = sqlx::query!(
             "SELECT auth_secret FROM users WHERE user_id = $1",
             user_id.0,
@@ -70,20 +469,29 @@ impl<'a> Transaction<'a> {
         .await?
         else {
             tracing::debug!("user not found");
-            return Ok(false);
+            return Ok(None);
         };
 
-        let password_ok = verify_auth_secret(row.auth_secret, auth_secret).await;
+        let password_ok = verify_secret(row.auth_secret, auth_secret.0).await;
 
         tracing::debug!(?password_ok);
 
-        Ok(password_ok)
+        Ok(password_ok.then_some(Login {
+            user_id,
+            method: LoginMethod::Secret,
+            device_id: None,
+        }))
     }
 
+    /// Checks the credentials, and records the login if they're valid.
+    ///
+    /// The client IP is used to rate limit password logins.
     pub async fn authenticate_user(
         &mut self,
         auth_request: AuthRequest,
-    ) -> Result<Option<UserId>, Error> {
+        client_ip: IpAddr,
+        user_agent: Option<&str>,
+    ) -> Result<Option<Login>, Error> {
         let auth_result = match auth_request {
             AuthRequest::Secret {
                 user_id,
@@ -91,20 +499,24 @@ impl<'a> Transaction<'a> {
             } => {
                 self.authenticate_user_with_secret(user_id, auth_secret)
                     .await?
-                    .then_some(user_id)
+            }
+            AuthRequest::Password {
+                login_name,
+                password,
+            } => {
+                self.authenticate_user_with_password(login_name, password, client_ip)
+                    .await?
             }
         };
 
-        if let Some(user_id) = auth_result {
-            sqlx::query!("UPDATE users SET last_login = utc_now()")
-                .execute(self.db())
-                .await?;
+        // the test user's secret is public.
+        let auth_result = auth_result.filter(|login| !self.is_disabled_test_user(login.user_id));
 
-            Ok(Some(user_id))
-        }
-        else {
-            Ok(None)
+        if let Some(login) = &auth_result {
+            self.insert_login_event(login, user_agent).await?;
         }
+
+        Ok(auth_result)
     }
 }
 
@@ -113,11 +525,37 @@ pub fn create_auth_secret() -> AuthSecret {
     AuthSecret(create_secret(LENGTH))
 }
 
-pub async fn hash_auth_secret(auth_secret: AuthSecret) -> String {
+pub fn create_access_token() -> AccessToken {
+    const LENGTH: usize = 43;
+    AccessToken(create_secret(LENGTH))
+}
+
+/// Hashes access tokens and device secrets.
+///
+/// These are random and long, so unlike user-chosen passwords, a fast hash is
+/// enough. This also lets us look them up by their hash.
+pub(super) fn hash_token(token: &str) -> Vec<u8> {
+    Sha256::digest(token.as_bytes()).to_vec()
+}
+
+/// Returns the login name in lower case, or `None` if it isn't a valid login
+/// name.
+pub fn canonicalize_login_name(login_name: &str) -> Option<String> {
+    let login_name = login_name.trim().to_lowercase();
+    let length = login_name.chars().count();
+    let valid = (MIN_LOGIN_NAME_LENGTH..=MAX_LOGIN_NAME_LENGTH).contains(&length)
+        && login_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    valid.then_some(login_name)
+}
+
+/// Hashes auth secrets and passwords with argon2.
+pub async fn hash_secret(secret: Secret<String>) -> String {
     tokio::task::spawn_blocking(move || {
         let salt = SaltString::generate(&mut thread_rng());
         Argon2::default()
-            .hash_password(auth_secret.0 .0.as_bytes(), &salt)
+            .hash_password(secret.0.as_bytes(), &salt)
             .unwrap()
             .to_string()
     })
@@ -125,17 +563,210 @@ pub async fn hash_auth_secret(auth_secret: AuthSecret) -> String {
     .unwrap()
 }
 
-pub async fn verify_auth_secret(auth_secret_hash: String, auth_secret: AuthSecret) -> bool {
+/// A hash that no password matches.
+async fn dummy_password_hash() -> String {
+    static DUMMY_PASSWORD_HASH: OnceCell<String> = OnceCell::const_new();
+    DUMMY_PASSWORD_HASH
+        .get_or_init(|| hash_secret(create_secret(32)))
+        .await
+        .clone()
+}
+
+pub async fn verify_secret(secret_hash: String, secret: Secret<String>) -> bool {
     tokio::task::spawn_blocking(move || {
-        let Ok(auth_secret_hash) = PasswordHash::new(&auth_secret_hash)
+        let Ok(secret_hash) = PasswordHash::new(&secret_hash)
         else {
-            tracing::warn!("failed to hash auth_secret");
+            tracing::warn!("failed to parse secret hash");
             return false;
         };
         Argon2::default()
-            .verify_password(auth_secret.0 .0.as_bytes(), &auth_secret_hash)
+            .verify_password(secret.0.as_bytes(), &secret_hash)
             .is_ok()
     })
     .await
     .unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::{
+        IpAddr,
+        Ipv4Addr,
+    };
+
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::game::Game;
+
+    const CLIENT_IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    fn password() -> Password {
+        Password("correct horse battery staple".to_owned().into())
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_rate_limits_password_logins_per_client_ip(pool: PgPool) {
+        let game = Game::new_for_test(pool).await.unwrap();
+        let (user_id, _) = game.create_user_for_test("alice").await.unwrap();
+        let other_client_ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+        let mut transaction = game.transaction().await.unwrap();
+        transaction
+            .set_password(user_id, "alice", password())
+            .await
+            .unwrap();
+
+        let login = |password| {
+            AuthRequest::Password {
+                // login names are canonicalized like when the password is set.
+                login_name: " Alice ".to_owned(),
+                password,
+            }
+        };
+
+        for _ in 0..MAX_FAILED_LOGINS_PER_WINDOW {
+            let wrong_password = Password("wrong horse battery staple".to_owned().into());
+            assert!(transaction
+                .authenticate_user(login(wrong_password), CLIENT_IP, None)
+                .await
+                .unwrap()
+                .is_none());
+        }
+
+        // even the correct password is rejected now, but only from that client IP.
+        let result = transaction
+            .authenticate_user(login(password()), CLIENT_IP, None)
+            .await;
+        assert!(matches!(result, Err(Error::Api(ApiError::RateLimited))));
+        let login = transaction
+            .authenticate_user(login(password()), other_client_ip, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(login.user_id, user_id);
+
+        transaction.commit().await.unwrap();
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_revokes_sessions(pool: PgPool) {
+        let game = Game::new_for_test(pool).await.unwrap();
+        let (user_id, _) = game.create_user_for_test("alice").await.unwrap();
+        let login = Login {
+            user_id,
+            method: LoginMethod::Secret,
+            device_id: None,
+        };
+
+        let mut transaction = game.transaction().await.unwrap();
+        let phone = transaction
+            .insert_access_token(login, Some("phone"))
+            .await
+            .unwrap();
+        let laptop = transaction
+            .insert_access_token(login, Some("laptop"))
+            .await
+            .unwrap();
+
+        let sessions = transaction
+            .fetch_sessions(user_id, Some(&phone))
+            .await
+            .unwrap();
+        assert_eq!(sessions.len(), 2);
+        let phone_session = sessions.iter().find(|session| session.current).unwrap();
+        assert_eq!(phone_session.user_agent.as_deref(), Some("phone"));
+
+        assert!(transaction
+            .delete_session(user_id, phone_session.session_id)
+            .await
+            .unwrap());
+        assert!(transaction
+            .authenticate_access_token(&phone)
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            transaction
+                .authenticate_access_token(&laptop)
+                .await
+                .unwrap(),
+            Some(user_id)
+        );
+
+        transaction.delete_sessions(user_id).await.unwrap();
+        assert!(transaction
+            .authenticate_access_token(&laptop)
+            .await
+            .unwrap()
+            .is_none());
+        transaction.commit().await.unwrap();
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_revokes_everything_when_rotating_the_secret(pool: PgPool) {
+        let game = Game::new_for_test(pool).await.unwrap();
+        let (user_id, auth_secret) = game.create_user_for_test("alice").await.unwrap();
+
+        let mut transaction = game.transaction().await.unwrap();
+        let (_, device_secret) = transaction
+            .insert_device(user_id, "phone".to_owned())
+            .await
+            .unwrap();
+        let access_token = transaction
+            .insert_access_token(
+                Login {
+                    user_id,
+                    method: LoginMethod::Secret,
+                    device_id: None,
+                },
+                None,
+            )
+            .await
+            .unwrap();
+        transaction
+            .set_password(user_id, "alice", password())
+            .await
+            .unwrap();
+
+        let new_auth_secret = transaction.rotate_auth_secret(user_id).await.unwrap();
+
+        assert!(transaction
+            .authenticate_access_token(&access_token)
+            .await
+            .unwrap()
+            .is_none());
+        for auth_request in [
+            AuthRequest::Secret {
+                user_id,
+                auth_secret,
+            },
+            AuthRequest::Secret {
+                user_id,
+                auth_secret: device_secret,
+            },
+            AuthRequest::Password {
+                login_name: "alice".to_owned(),
+                password: password(),
+            },
+        ] {
+            assert!(transaction
+                .authenticate_user(auth_request, CLIENT_IP, None)
+                .await
+                .unwrap()
+                .is_none());
+        }
+        assert!(transaction
+            .authenticate_user(
+                AuthRequest::Secret {
+                    user_id,
+                    auth_secret: new_auth_secret,
+                },
+                CLIENT_IP,
+                None,
+            )
+            .await
+            .unwrap()
+            .is_some());
+    }
+}
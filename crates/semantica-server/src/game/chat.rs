@@ -0,0 +1,150 @@
+use chrono::{
+    Duration,
+    NaiveDateTime,
+};
+use semantica_protocol::{
+    chat::{
+        ChatMessage,
+        MessageId,
+    },
+    error::ApiError,
+    node::NodeId,
+    pagination::Pagination,
+    user::{
+        UserId,
+        UserLink,
+    },
+};
+use uuid::Uuid;
+
+use super::Transaction;
+use crate::{
+    error::Error,
+    utils::convert::{
+        FromDb,
+        ToDb,
+    },
+};
+
+const MAX_MESSAGES_PER_PAGE: usize = 100;
+
+/// How many messages a user can send within the rate limit window.
+const MAX_MESSAGES_PER_WINDOW: i64 = 5;
+
+const RATE_LIMIT_WINDOW_SECONDS: i64 = 10;
+
+impl<'a> Transaction<'a> {
+    /// Fails with [`ApiError::RateLimited`] if the user sent too many messages
+    /// recently.
+    ///
+    /// This locks the user's row, so that concurrent messages by the same user
+    /// are counted correctly.
+    pub async fn check_chat_rate_limit(&mut self, user_id: UserId) -> Result<(), Error> {
+        sqlx::query!(
+            "SELECT user_id FROM users WHERE user_id = $1 FOR UPDATE",
+            ToDb::<Uuid>::to_db(&user_id)?,
+        )
+        .fetch_one(self.db())
+        .await?;
+
+        let since = self.now() - Duration::seconds(RATE_LIMIT_WINDOW_SECONDS);
+        let count = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) AS "count!"
+            FROM chat_messages
+            WHERE user_id = $1 AND created_at > $2
+            "#,
+            ToDb::<Uuid>::to_db(&user_id)?,
+            since.naive_utc(),
+        )
+        .fetch_one(self.db())
+        .await?;
+
+        if count >= MAX_MESSAGES_PER_WINDOW {
+            Err(ApiError::RateLimited.into())
+        }
+        else {
+            Ok(())
+        }
+    }
+
+    pub async fn insert_chat_message(
+        &mut self,
+        node_id: NodeId,
+        author: UserLink,
+        text: String,
+    ) -> Result<ChatMessage, Error> {
+        let message = ChatMessage {
+            message_id: MessageId(Uuid::new_v4()),
+            node_id,
+            author,
+            text,
+            created_at: self.now(),
+        };
+
+        sqlx::query!(
+            r#"
+            INSERT INTO chat_messages (
+                message_id,
+                node_id,
+                user_id,
+                text,
+                created_at
+            ) VALUES ($1, $2, $3, $4, $5)
+            "#,
+            message.message_id.0,
+            ToDb::<Uuid>::to_db(&message.node_id)?,
+            ToDb::<Uuid>::to_db(&message.author.user_id)?,
+            &message.text,
+            ToDb::<NaiveDateTime>::to_db(&message.created_at)?,
+        )
+        .execute(self.db())
+        .await?;
+
+        Ok(message)
+    }
+
+    /// Fetches the chat messages of a node, newest first.
+    pub async fn fetch_chat_messages(
+        &mut self,
+        node_id: NodeId,
+        pagination: Pagination,
+    ) -> Result<Vec<ChatMessage>, Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT
+                chat_messages.message_id AS message_id,
+                chat_messages.text AS text,
+                chat_messages.created_at AS created_at,
+                users.user_id AS user_id,
+                users.name AS name
+            FROM chat_messages
+                INNER JOIN users ON chat_messages.user_id = users.user_id
+            WHERE chat_messages.node_id = $1
+            ORDER BY chat_messages.created_at DESC, chat_messages.message_id ASC
+            OFFSET $2
+            LIMIT $3
+            "#,
+            ToDb::<Uuid>::to_db(&node_id)?,
+            ToDb::<i64>::to_db(&pagination.offset)?,
+            ToDb::<i64>::to_db(&pagination.limit.min(MAX_MESSAGES_PER_PAGE))?,
+        )
+        .fetch_all(self.db())
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(ChatMessage {
+                    message_id: MessageId(row.message_id),
+                    node_id,
+                    author: UserLink {
+                        user_id: row.user_id.from_db()?,
+                        name: row.name,
+                    },
+                    text: row.text,
+                    created_at: row.created_at.from_db()?,
+                })
+            })
+            .collect()
+    }
+}
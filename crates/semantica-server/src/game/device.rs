@@ -0,0 +1,138 @@
+use chrono::NaiveDateTime;
+use semantica_protocol::{
+    auth::{
+        AuthSecret,
+        Device,
+        DeviceId,
+    },
+    user::UserId,
+};
+use uuid::Uuid;
+
+use super::{
+    auth::{
+        create_auth_secret,
+        hash_token,
+    },
+    Transaction,
+};
+use crate::{
+    error::Error,
+    utils::convert::{
+        FromDb,
+        ToDb,
+    },
+};
+
+impl<'a> Transaction<'a> {
+    /// Creates an extra auth secret for one of the user's devices.
+    pub async fn insert_device(
+        &mut self,
+        user_id: UserId,
+        name: String,
+    ) -> Result<(Device, AuthSecret), Error> {
+        let auth_secret = create_auth_secret();
+        let device = Device {
+            device_id: DeviceId(Uuid::new_v4()),
+            name,
+            created_at: self.now(),
+            last_used: None,
+        };
+
+        sqlx::query!(
+            r#"
+            INSERT INTO device_secrets (
+                device_id,
+                user_id,
+                name,
+                secret_hash,
+                created_at
+            ) VALUES ($1, $2, $3, $4, $5)
+            "#,
+            device.device_id.0,
+            user_id.0,
+            &device.name,
+            hash_token(&auth_secret.0 .0),
+            ToDb::<NaiveDateTime>::to_db(&device.created_at)?,
+        )
+        .execute(self.db())
+        .await?;
+
+        Ok((device, auth_secret))
+    }
+
+    pub async fn fetch_devices(&mut self, user_id: UserId) -> Result<Vec<Device>, Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT device_id, name, created_at, last_used
+            FROM device_secrets
+            WHERE user_id = $1
+            ORDER BY created_at ASC
+            "#,
+            user_id.0,
+        )
+        .fetch_all(self.db())
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(Device {
+                    device_id: DeviceId(row.device_id),
+                    name: row.name,
+                    created_at: row.created_at.from_db()?,
+                    last_used: row.last_used.from_db()?,
+                })
+            })
+            .collect()
+    }
+
+    /// Revokes a device secret, together with the access tokens that were
+    /// issued for it.
+    ///
+    /// Returns whether the device existed.
+    pub async fn delete_device(
+        &mut self,
+        user_id: UserId,
+        device_id: DeviceId,
+    ) -> Result<bool, Error> {
+        let result = sqlx::query!(
+            "DELETE FROM device_secrets WHERE device_id = $1 AND user_id = $2",
+            device_id.0,
+            user_id.0,
+        )
+        .execute(self.db())
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Revokes all of the user's device secrets, together with the access
+    /// tokens that were issued for them.
+    pub async fn delete_devices(&mut self, user_id: UserId) -> Result<(), Error> {
+        sqlx::query!("DELETE FROM device_secrets WHERE user_id = $1", user_id.0)
+            .execute(self.db())
+            .await?;
+        Ok(())
+    }
+
+    /// Checks whether the secret is one of the user's device secrets.
+    pub(super) async fn authenticate_device_secret(
+        &mut self,
+        user_id: UserId,
+        auth_secret: &AuthSecret,
+    ) -> Result<Option<DeviceId>, Error> {
+        let device_id = sqlx::query_scalar!(
+            r#"
+            UPDATE device_secrets
+            SET last_used = $1
+            WHERE user_id = $2 AND secret_hash = $3
+            RETURNING device_id
+            "#,
+            self.now().naive_utc(),
+            user_id.0,
+            hash_token(&auth_secret.0 .0),
+        )
+        .fetch_optional(self.db())
+        .await?;
+        Ok(device_id.map(DeviceId))
+    }
+}
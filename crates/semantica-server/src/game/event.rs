@@ -0,0 +1,262 @@
+use chrono::{
+    DateTime,
+    Utc,
+};
+use semantica_protocol::{
+    event::Event,
+    node::NodeId,
+    user::UserId,
+};
+use uuid::{
+    uuid,
+    Uuid,
+};
+
+use super::Transaction;
+use crate::error::Error;
+
+/// How many events are buffered for subscribers that can't keep up.
+pub const CHANNEL_CAPACITY: usize = 1024;
+
+/// Events are sequentially numbered by the database, in the order in which
+/// they were committed.
+pub type EventId = i64;
+
+/// The advisory lock that is held while events are logged, until the
+/// transaction commits.
+const EVENT_LOG_LOCK: i64 = 0x6576656e7473;
+
+/// The property that stores the ID of the latest deleted event.
+const DELETED_EVENT_ID: Uuid = uuid!("5d4a3f0c-2a5e-4c52-9a8f-0f6b2f1e7c31");
+
+/// The Postgres channel on which the IDs of new events are sent, so that every
+/// server instance can deliver them to its subscribers.
+pub const NOTIFY_CHANNEL: &str = "events";
+
+/// Decides which events are relevant to a user.
+///
+/// This keeps track of the node the user is in, so it needs to see all events
+/// in order.
+#[derive(Clone, Debug)]
+pub struct EventFilter {
+    user_id: UserId,
+    in_node: NodeId,
+}
+
+impl EventFilter {
+    pub fn new(user_id: UserId, in_node: NodeId) -> Self {
+        Self { user_id, in_node }
+    }
+
+    pub fn is_relevant(&mut self, event: &Event) -> bool {
+        match event {
+            Event::SpellDiscovered { .. } => true,
+            Event::NodeCreated { parent_id, .. } => *parent_id == self.in_node,
+            Event::PlayerEntered {
+                node_id,
+                user,
+                hidden,
+            } => {
+                if user.user_id == self.user_id {
+                    self.in_node = *node_id;
+                    true
+                }
+                else {
+                    !hidden && *node_id == self.in_node
+                }
+            }
+            Event::PlayerLeft {
+                node_id,
+                user,
+                hidden,
+            } => user.user_id == self.user_id || (!hidden && *node_id == self.in_node),
+            Event::ChatMessage { message } => message.node_id == self.in_node,
+            Event::InventoryChanged { user_id } => *user_id == self.user_id,
+            Event::Resync => true,
+        }
+    }
+}
+
+impl<'a> Transaction<'a> {
+    /// Logs and publishes an event once the transaction is committed.
+    ///
+    /// If the transaction is rolled back, the event is discarded.
+    pub fn publish(&mut self, event: Event) {
+        self.pending_events.push(event);
+    }
+
+    /// Logs the pending events and notifies all server instances of them.
+    ///
+    /// This must be called right before the transaction is committed.
+    /// Subscribers resume after the last event ID they've seen, so event IDs
+    /// must be assigned in commit order. Otherwise an event with a lower ID
+    /// could become visible after one with a higher ID, and would be skipped.
+    /// So only one transaction at a time can log events, and it holds the lock
+    /// until it commits.
+    ///
+    /// Postgres only delivers the notifications when the transaction is
+    /// committed, and delivers them in commit order.
+    pub(super) async fn log_pending_events(&mut self) -> Result<(), Error> {
+        let events = std::mem::take(&mut self.pending_events);
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        sqlx::query!("SELECT pg_advisory_xact_lock($1)", EVENT_LOG_LOCK)
+            .execute(self.db())
+            .await?;
+
+        for event in events {
+            let event_id = self.insert_event(&event).await?;
+            sqlx::query!(
+                "SELECT pg_notify($1, $2)",
+                NOTIFY_CHANNEL,
+                event_id.to_string(),
+            )
+            .execute(self.db())
+            .await?;
+        }
+        Ok(())
+    }
+
+    async fn insert_event(&mut self, event: &Event) -> Result<EventId, Error> {
+        let event_id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO events (
+                transaction_id,
+                created_at,
+                data
+            ) VALUES ($1, $2, $3)
+            RETURNING event_id
+            "#,
+            self.id,
+            self.now.naive_utc(),
+            serde_json::to_value(event)?,
+        )
+        .fetch_one(self.db())
+        .await?;
+        Ok(event_id)
+    }
+
+    /// Fetches the events that were logged after `event_id`, in order.
+    pub async fn fetch_events_after(
+        &mut self,
+        event_id: EventId,
+        limit: usize,
+    ) -> Result<Vec<(EventId, Event)>, Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT event_id, data
+            FROM events
+            WHERE event_id > $1
+            ORDER BY event_id ASC
+            LIMIT $2
+            "#,
+            event_id,
+            limit as i64,
+        )
+        .fetch_all(self.db())
+        .await?;
+
+        rows.into_iter()
+            .map(|row| Ok((row.event_id, serde_json::from_value(row.data)?)))
+            .collect()
+    }
+
+    /// Returns the ID of the latest event, or 0 if no events were logged yet.
+    pub async fn fetch_latest_event_id(&mut self) -> Result<EventId, Error> {
+        let latest_event_id =
+            sqlx::query_scalar!(r#"SELECT COALESCE(MAX(event_id), 0) AS "event_id!" FROM events"#)
+                .fetch_one(self.db())
+                .await?;
+        // all events might have been deleted.
+        Ok(latest_event_id.max(self.fetch_deleted_event_id().await?))
+    }
+
+    /// Returns the ID of the latest event that was deleted, or 0.
+    ///
+    /// Subscribers that haven't seen all events up to this one can't catch up
+    /// anymore.
+    pub async fn fetch_deleted_event_id(&mut self) -> Result<EventId, Error> {
+        let value = sqlx::query_scalar!(
+            "SELECT value FROM properties WHERE key = $1",
+            DELETED_EVENT_ID,
+        )
+        .fetch_optional(self.db())
+        .await?;
+        Ok(value
+            .map(serde_json::from_value)
+            .transpose()?
+            .unwrap_or_default())
+    }
+
+    /// Deletes events that were logged before `time`.
+    pub async fn delete_events_before(&mut self, time: DateTime<Utc>) -> Result<u64, Error> {
+        let row = sqlx::query!(
+            r#"
+            WITH deleted AS (
+                DELETE FROM events WHERE created_at < $1 RETURNING event_id
+            )
+            SELECT COUNT(*) AS "count!", MAX(event_id) AS max_event_id
+            FROM deleted
+            "#,
+            time.naive_utc(),
+        )
+        .fetch_one(self.db())
+        .await?;
+
+        if let Some(max_event_id) = row.max_event_id {
+            let deleted_event_id = self.fetch_deleted_event_id().await?.max(max_event_id);
+            self.set_property(Some(DELETED_EVENT_ID), &deleted_event_id)
+                .await?;
+        }
+
+        Ok(row.count as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::PgPool;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::game::Game;
+
+    async fn publish_inventory_changed(transaction: &mut Transaction<'_>) -> UserId {
+        let user_id = UserId(Uuid::new_v4());
+        transaction.publish(Event::InventoryChanged { user_id });
+        user_id
+    }
+
+    async fn find_event_after(game: &Game, after: EventId, user_id: UserId) -> Option<EventId> {
+        let mut transaction = game.transaction().await.unwrap();
+        let events = transaction.fetch_events_after(after, 100).await.unwrap();
+        transaction.commit().await.unwrap();
+        events.into_iter().find_map(|(event_id, event)| {
+            matches!(event, Event::InventoryChanged { user_id: id } if id == user_id)
+                .then_some(event_id)
+        })
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_numbers_events_in_commit_order(pool: PgPool) {
+        let game = Game::new_for_test(pool).await.unwrap();
+
+        let mut first = game.transaction().await.unwrap();
+        let first_user_id = publish_inventory_changed(&mut first).await;
+        let mut second = game.transaction().await.unwrap();
+        let second_user_id = publish_inventory_changed(&mut second).await;
+
+        // the second transaction commits first, and a subscriber sees its event.
+        second.commit().await.unwrap();
+        let last_seen = find_event_after(&game, 0, second_user_id).await.unwrap();
+
+        // when the subscriber resumes after the last event it has seen, it must
+        // still get the event of the first transaction.
+        first.commit().await.unwrap();
+        assert!(find_event_after(&game, last_seen, first_user_id)
+            .await
+            .is_some());
+    }
+}
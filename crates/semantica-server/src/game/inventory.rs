@@ -103,4 +103,68 @@ impl<'a> Transaction<'a> {
 
         Ok(spell_amount)
     }
+
+    /// Removes spells from a user's inventory.
+    ///
+    /// Returns the remaining amount, or `None` if the user doesn't have enough
+    /// of the spell. In that case the inventory is left unchanged.
+    pub async fn remove_from_inventory<Spell: Links<SpellId>>(
+        &mut self,
+        user_id: UserId,
+        mut spell_amount: SpellAmount<Spell>,
+    ) -> Result<Option<SpellAmount<Spell>>, Error> {
+        let user_id = ToDb::<Uuid>::to_db(&user_id)?;
+        let spell_id = ToDb::<Uuid>::to_db(&spell_amount.id())?;
+
+        let Some(amount): Option<usize> = sqlx::query_scalar!(
+            r#"
+            SELECT amount
+            FROM inventory_contents
+            WHERE user_id = $1 AND spell_id = $2
+            FOR UPDATE
+            "#,
+            user_id,
+            spell_id,
+        )
+        .fetch_optional(self.db())
+        .await?
+        .from_db()?
+        else {
+            return Ok(None);
+        };
+
+        if amount < spell_amount.amount {
+            return Ok(None);
+        }
+
+        // amounts in the inventory must be positive, so we delete the row if none
+        // are left.
+        if amount == spell_amount.amount {
+            sqlx::query!(
+                "DELETE FROM inventory_contents WHERE user_id = $1 AND spell_id = $2",
+                user_id,
+                spell_id,
+            )
+            .execute(self.db())
+            .await?;
+        }
+        else {
+            sqlx::query!(
+                r#"
+                UPDATE inventory_contents
+                SET amount = amount - $3
+                WHERE user_id = $1 AND spell_id = $2
+                "#,
+                user_id,
+                spell_id,
+                ToDb::<i32>::to_db(&spell_amount.amount)?,
+            )
+            .execute(self.db())
+            .await?;
+        }
+
+        spell_amount.amount = amount - spell_amount.amount;
+
+        Ok(Some(spell_amount))
+    }
 }
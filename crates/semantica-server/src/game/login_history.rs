@@ -0,0 +1,126 @@
+use chrono::{
+    DateTime,
+    Utc,
+};
+use semantica_protocol::{
+    auth::{
+        DeviceId,
+        LoginEvent,
+    },
+    pagination::Pagination,
+    user::{
+        UserId,
+        UserLink,
+    },
+};
+use uuid::Uuid;
+
+use super::{
+    auth::Login,
+    Transaction,
+};
+use crate::{
+    error::Error,
+    utils::convert::{
+        FromDb,
+        ToDb,
+    },
+};
+
+const MAX_LOGIN_EVENTS_PER_PAGE: usize = 100;
+
+impl<'a> Transaction<'a> {
+    /// Records a successful login.
+    pub async fn insert_login_event(
+        &mut self,
+        login: &Login,
+        user_agent: Option<&str>,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            "UPDATE users SET last_login = $1 WHERE user_id = $2",
+            self.now().naive_utc(),
+            ToDb::<Uuid>::to_db(&login.user_id)?,
+        )
+        .execute(self.db())
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO login_events (
+                user_id,
+                method,
+                device_id,
+                user_agent,
+                created_at
+            ) VALUES ($1, $2, $3, $4, $5)
+            "#,
+            ToDb::<Uuid>::to_db(&login.user_id)?,
+            ToDb::<String>::to_db(&login.method)?,
+            login.device_id.map(|device_id| device_id.0),
+            user_agent,
+            self.now().naive_utc(),
+        )
+        .execute(self.db())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Fetches recent logins, newest first.
+    ///
+    /// If no user is given, this returns the logins of all users.
+    pub async fn fetch_login_events(
+        &mut self,
+        user_id: Option<UserId>,
+        pagination: Pagination,
+    ) -> Result<Vec<LoginEvent>, Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT
+                login_events.method AS method,
+                login_events.device_id AS device_id,
+                login_events.user_agent AS user_agent,
+                login_events.created_at AS created_at,
+                users.user_id AS user_id,
+                users.name AS name
+            FROM login_events
+                INNER JOIN users ON login_events.user_id = users.user_id
+            WHERE $1::UUID IS NULL OR login_events.user_id = $1
+            ORDER BY login_events.created_at DESC, login_events.login_event_id DESC
+            OFFSET $2
+            LIMIT $3
+            "#,
+            ToDb::<Option<Uuid>>::to_db(&user_id)?,
+            ToDb::<i64>::to_db(&pagination.offset)?,
+            ToDb::<i64>::to_db(&pagination.limit.min(MAX_LOGIN_EVENTS_PER_PAGE))?,
+        )
+        .fetch_all(self.db())
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(LoginEvent {
+                    user: UserLink {
+                        user_id: row.user_id.from_db()?,
+                        name: row.name,
+                    },
+                    method: row.method.from_db()?,
+                    device_id: row.device_id.map(DeviceId),
+                    user_agent: row.user_agent,
+                    created_at: row.created_at.from_db()?,
+                })
+            })
+            .collect()
+    }
+
+    /// Deletes logins that were recorded before `time`.
+    pub async fn delete_login_events_before(&mut self, time: DateTime<Utc>) -> Result<u64, Error> {
+        let result = sqlx::query!(
+            "DELETE FROM login_events WHERE created_at < $1",
+            time.naive_utc(),
+        )
+        .execute(self.db())
+        .await?;
+        Ok(result.rows_affected())
+    }
+}
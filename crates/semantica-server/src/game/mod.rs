@@ -1,12 +1,19 @@
 pub mod ai;
 pub mod auth;
+pub mod chat;
+pub mod device;
+pub mod event;
 pub mod inventory;
+pub mod login_history;
 pub mod node;
+pub mod presence;
+pub mod recipe;
 pub mod spell;
 
 use std::{
     net::SocketAddr,
     sync::Arc,
+    time::Duration,
 };
 
 use axum::{
@@ -22,7 +29,16 @@ use chrono::{
 };
 use semantica_protocol::{
     auth::AuthSecret,
-    spell::SpellAmount,
+    error::ApiError,
+    event::Event,
+    node::NodeId,
+    spell::{
+        RecipeId,
+        Spell,
+        SpellAmount,
+        SpellId,
+    },
+    user::UserId,
 };
 use serde::{
     Deserialize,
@@ -31,6 +47,7 @@ use serde::{
 use shuttle_runtime::CustomError;
 use shuttle_secrets::SecretStore;
 use sqlx::{
+    postgres::PgListener,
     PgConnection,
     PgPool,
     Postgres,
@@ -38,6 +55,7 @@ use sqlx::{
 use tokio::{
     net::TcpListener,
     signal,
+    sync::broadcast,
     task::AbortHandle,
 };
 use tower_http::{
@@ -54,6 +72,7 @@ use tower_http::{
 };
 use tower_layer::Layer;
 use tower_sessions::{
+    cookie::SameSite,
     ExpiredDeletion,
     Expiry,
     SessionManagerLayer,
@@ -69,18 +88,153 @@ use crate::{
     error::Error,
     game::{
         ai::Ai,
+        event::{
+            EventId,
+            CHANNEL_CAPACITY,
+            NOTIFY_CHANNEL,
+        },
         node::{
             create_node_content,
             create_root_node,
+            ChildKey,
+        },
+        recipe::CraftedProduct,
+        spell::{
+            create_spell,
+            get_spell_id_for_name,
         },
-        spell::create_spell,
     },
+    utils::single_flight::SingleFlight,
 };
 
+/// How long events are kept for clients to catch up, unless configured with
+/// `EVENT_RETENTION_HOURS`.
+const DEFAULT_EVENT_RETENTION_HOURS: i64 = 24;
+
+/// How long logins are kept in the login history, unless configured with
+/// `LOGIN_EVENT_RETENTION_DAYS`.
+const DEFAULT_LOGIN_EVENT_RETENTION_DAYS: i64 = 90;
+
+/// How long access tokens stay valid without being used, unless configured
+/// with `SESSION_EXPIRY_HOURS`.
+const DEFAULT_ACCESS_TOKEN_LIFETIME_DAYS: i64 = 30;
+
+/// The user that is created in dev mode, and that is used for unauthenticated
+/// requests.
+///
+/// Its secret is public, so it can't log in when dev mode is off.
+pub const TEST_USER_ID: Uuid = uuid!("43d65ac1-2778-49e8-b28d-65c7334cec32");
+
+/// The spells that the game starts with.
+const INITIAL_SPELLS: &[(&str, &str)] = &[
+    ("Wind", "🌬️"),
+    ("Earth", "🌍"),
+    ("Fire", "🔥"),
+    ("Water", "🌊"),
+];
+
+/// How often old events, expired access tokens and old logins are deleted.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often users that aren't active anymore are announced as leaving.
+const PRESENCE_EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+
+const EVENT_LISTENER_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many logged events the event listener fetches at once.
+const EVENT_LISTENER_BATCH_SIZE: usize = 100;
+
 #[derive(Debug)]
 struct Inner {
     pool: PgPool,
     ai: Ai,
+    crafting_flights: SingleFlight<RecipeId, CraftedProduct>,
+    node_flights: SingleFlight<ChildKey, NodeId>,
+    events: broadcast::Sender<(EventId, Event)>,
+    event_retention: chrono::Duration,
+    login_event_retention: chrono::Duration,
+    dev_mode: bool,
+    behind_proxy: bool,
+    session_config: SessionConfig,
+}
+
+/// How session cookies are set.
+///
+/// This is configured with `SESSION_EXPIRY_HOURS`, `SESSION_COOKIE_SECURE` and
+/// `SESSION_COOKIE_SAME_SITE`.
+#[derive(Clone, Debug)]
+struct SessionConfig {
+    /// By default sessions end when the browser is closed. With
+    /// `SESSION_EXPIRY_HOURS` they instead expire after that much inactivity.
+    expiry: Expiry,
+    /// How long access tokens stay valid without being used. This is
+    /// `SESSION_EXPIRY_HOURS` too, so that sessions and the access tokens
+    /// stored in them expire together. Defaults to 30 days.
+    access_token_lifetime: chrono::Duration,
+    /// Whether the cookie is only sent over HTTPS. Defaults to `false`.
+    secure: bool,
+    /// Defaults to `strict`.
+    same_site: SameSite,
+}
+
+impl SessionConfig {
+    fn new(secrets: &SecretStore) -> Result<Self, Error> {
+        let (expiry, access_token_lifetime) = match secrets.get("SESSION_EXPIRY_HOURS") {
+            Some(value) => {
+                let hours = value.parse().map_err(|_| {
+                    Error::Config {
+                        key: "SESSION_EXPIRY_HOURS",
+                        value,
+                    }
+                })?;
+                (
+                    Expiry::OnInactivity(time::Duration::hours(hours)),
+                    chrono::Duration::hours(hours),
+                )
+            }
+            None => {
+                (
+                    Expiry::OnSessionEnd,
+                    chrono::Duration::days(DEFAULT_ACCESS_TOKEN_LIFETIME_DAYS),
+                )
+            }
+        };
+
+        let secure = bool_config(secrets, "SESSION_COOKIE_SECURE")?;
+
+        let same_site = match secrets.get("SESSION_COOKIE_SAME_SITE").as_deref() {
+            None | Some("strict") => SameSite::Strict,
+            Some("lax") => SameSite::Lax,
+            Some("none") => SameSite::None,
+            Some(value) => {
+                return Err(Error::Config {
+                    key: "SESSION_COOKIE_SAME_SITE",
+                    value: value.to_owned(),
+                })
+            }
+        };
+
+        Ok(Self {
+            expiry,
+            access_token_lifetime,
+            secure,
+            same_site,
+        })
+    }
+}
+
+/// Parses a `true`/`false` config value, which defaults to `false`.
+fn bool_config(secrets: &SecretStore, key: &'static str) -> Result<bool, Error> {
+    match secrets.get(key).as_deref() {
+        None | Some("false") => Ok(false),
+        Some("true") => Ok(true),
+        Some(value) => {
+            Err(Error::Config {
+                key,
+                value: value.to_owned(),
+            })
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -90,12 +244,106 @@ pub struct Game {
 
 impl Game {
     pub async fn new(pool: PgPool, secrets: SecretStore) -> Result<Self, Error> {
-        sqlx::migrate!().run(&pool).await?;
+        let ai = Ai::new(&secrets)?;
+
+        let event_retention_hours = match secrets.get("EVENT_RETENTION_HOURS") {
+            Some(value) => {
+                value.parse().map_err(|_| {
+                    Error::Config {
+                        key: "EVENT_RETENTION_HOURS",
+                        value,
+                    }
+                })?
+            }
+            None => DEFAULT_EVENT_RETENTION_HOURS,
+        };
+        let event_retention = chrono::Duration::hours(event_retention_hours);
+
+        let login_event_retention_days = match secrets.get("LOGIN_EVENT_RETENTION_DAYS") {
+            Some(value) => {
+                value.parse().map_err(|_| {
+                    Error::Config {
+                        key: "LOGIN_EVENT_RETENTION_DAYS",
+                        value,
+                    }
+                })?
+            }
+            None => DEFAULT_LOGIN_EVENT_RETENTION_DAYS,
+        };
+        let login_event_retention = chrono::Duration::days(login_event_retention_days);
+
+        let dev_mode = bool_config(&secrets, "DEV_MODE")?;
+        if dev_mode {
+            tracing::warn!("dev mode enabled: unauthenticated requests act as the test user");
+        }
+
+        let behind_proxy = bool_config(&secrets, "BEHIND_PROXY")?;
+
+        let session_config = SessionConfig::new(&secrets)?;
+
+        Self::with_inner(Inner {
+            pool,
+            ai,
+            crafting_flights: Default::default(),
+            node_flights: Default::default(),
+            events: broadcast::channel(CHANNEL_CAPACITY).0,
+            event_retention,
+            login_event_retention,
+            dev_mode,
+            behind_proxy,
+            session_config,
+        })
+        .await
+    }
+
+    /// Creates a game in dev mode with the scripted AI backend.
+    #[cfg(test)]
+    pub(crate) async fn new_for_test(pool: PgPool) -> Result<Self, Error> {
+        Self::with_inner(Inner {
+            pool,
+            ai: Ai::scripted(),
+            crafting_flights: Default::default(),
+            node_flights: Default::default(),
+            events: broadcast::channel(CHANNEL_CAPACITY).0,
+            event_retention: chrono::Duration::hours(DEFAULT_EVENT_RETENTION_HOURS),
+            login_event_retention: chrono::Duration::days(DEFAULT_LOGIN_EVENT_RETENTION_DAYS),
+            dev_mode: true,
+            behind_proxy: false,
+            session_config: SessionConfig {
+                expiry: Expiry::OnSessionEnd,
+                access_token_lifetime: chrono::Duration::days(DEFAULT_ACCESS_TOKEN_LIFETIME_DAYS),
+                secure: false,
+                same_site: SameSite::Strict,
+            },
+        })
+        .await
+    }
+
+    /// Creates a user with a random ID and auth secret.
+    #[cfg(test)]
+    pub(crate) async fn create_user_for_test(
+        &self,
+        name: &str,
+    ) -> Result<(UserId, AuthSecret), Error> {
+        let user_id = UserId(Uuid::new_v4());
+        let auth_secret = auth::create_auth_secret();
+        let mut transaction = self.transaction().await?;
+        transaction
+            .insert_user(user_id, name, auth_secret.clone())
+            .await?;
+        transaction.commit().await?;
+        Ok((user_id, auth_secret))
+    }
 
-        let ai = Ai::new(&secrets);
+    async fn with_inner(inner: Inner) -> Result<Self, Error> {
+        let mut migrator = sqlx::migrate!();
+        // databases migrated by development versions might have applied migrations
+        // that were removed since.
+        migrator.set_ignore_missing(true);
+        migrator.run(&inner.pool).await?;
 
         let this = Self {
-            inner: Arc::new(Inner { pool, ai }),
+            inner: Arc::new(inner),
         };
 
         this.initialize().await?;
@@ -110,6 +358,7 @@ impl Game {
             game: self.clone(),
             transaction,
             now: Utc::now(),
+            pending_events: vec![],
         })
     }
 
@@ -117,10 +366,176 @@ impl Game {
         &self.inner.ai
     }
 
+    /// Whether unauthenticated requests are treated as coming from the test
+    /// user.
+    pub fn dev_mode(&self) -> bool {
+        self.inner.dev_mode
+    }
+
+    /// Whether requests come through a reverse proxy, which adds the client IP
+    /// to the `X-Forwarded-For` header.
+    pub fn behind_proxy(&self) -> bool {
+        self.inner.behind_proxy
+    }
+
+    /// How long access tokens stay valid without being used.
+    pub fn access_token_lifetime(&self) -> chrono::Duration {
+        self.inner.session_config.access_token_lifetime
+    }
+
     pub fn pool(&self) -> &PgPool {
         &self.inner.pool
     }
 
+    /// Sends an event to all subscribers of this instance.
+    ///
+    /// This is called when we're notified of a committed event by Postgres.
+    fn publish(&self, event_id: EventId, event: Event) {
+        tracing::debug!(event_id, ?event, "publishing event");
+        // this only fails if there are no subscribers.
+        let _ = self.inner.events.send((event_id, event));
+    }
+
+    /// Subscribes to all events, together with their IDs.
+    pub fn subscribe(&self) -> broadcast::Receiver<(EventId, Event)> {
+        self.inner.events.subscribe()
+    }
+
+    /// Returns the product of a recipe, and generates it if nobody crafted the
+    /// recipe yet.
+    ///
+    /// Concurrent calls for the same recipe share one generation. It commits
+    /// the product before it finishes, so that later calls find it in the
+    /// database. No transaction is open while we wait for the AI.
+    pub async fn craft_product(
+        &self,
+        recipe_id: RecipeId,
+        ingredients: Vec<SpellId>,
+        crafted_by: UserId,
+    ) -> Result<CraftedProduct, Error> {
+        let game = self.clone();
+        self.inner
+            .crafting_flights
+            .run(recipe_id, async move {
+                let mut transaction = game.transaction().await?;
+                if let Some(product_id) = transaction.fetch_recipe_product(recipe_id).await? {
+                    transaction.commit().await?;
+                    return Ok(CraftedProduct {
+                        product_id,
+                        discovered_by: None,
+                    });
+                }
+                let spells = transaction.fetch_spells(&ingredients).await?;
+                transaction.commit().await?;
+
+                let mut ingredient_names = ingredients
+                    .iter()
+                    .filter_map(|spell_id| spells.get(spell_id))
+                    .map(|spell| spell.name.as_str())
+                    .collect::<Vec<_>>();
+                ingredient_names.sort();
+                let crafting_result = game.ai().craft(&ingredient_names).await?;
+
+                let mut transaction = game.transaction().await?;
+                let crafted_product = if let Some(product_id) =
+                    transaction.lock_recipe(recipe_id, &ingredients).await?
+                {
+                    // another server instance crafted this recipe in the meantime.
+                    CraftedProduct {
+                        product_id,
+                        discovered_by: None,
+                    }
+                }
+                else {
+                    // the product might be a spell that already exists under a slightly
+                    // different name, in which case we reuse it.
+                    let product = Spell {
+                        created_at: Some(transaction.now()),
+                        created_by: Some(crafted_by),
+                        ..create_spell(
+                            crafting_result.name,
+                            crafting_result.emoji,
+                            crafting_result.description,
+                        )
+                    };
+                    let (product_id, is_new_spell) =
+                        transaction.insert_or_get_spell(&product).await?;
+                    transaction
+                        .set_recipe_product(recipe_id, product_id)
+                        .await?;
+
+                    if is_new_spell {
+                        let spell = transaction.fetch_spell(product_id).await?;
+                        transaction.publish(Event::SpellDiscovered { spell, recipe_id });
+                    }
+
+                    CraftedProduct {
+                        product_id,
+                        discovered_by: Some(crafted_by),
+                    }
+                };
+                transaction.commit().await?;
+
+                Ok(crafted_product)
+            })
+            .await
+    }
+
+    /// Returns the ID of a child node, and generates the child if it doesn't
+    /// exist yet.
+    ///
+    /// Concurrent calls for the same child share one generation. It commits
+    /// the child before it finishes, so that later calls find it in the
+    /// database. No transaction is open while we wait for the AI.
+    pub async fn generate_child_node(
+        &self,
+        key: ChildKey,
+        created_by: UserId,
+    ) -> Result<NodeId, Error> {
+        let game = self.clone();
+        self.inner
+            .node_flights
+            .run(key, async move {
+                let mut transaction = game.transaction().await?;
+                if let Some(child_id) = transaction.fetch_child_id(&key).await? {
+                    transaction.commit().await?;
+                    return Ok(child_id);
+                }
+                let parent = transaction.fetch_node(key.parent_id).await?;
+                let fork = match key.fork {
+                    Some(fork) => Some((fork.position, transaction.fetch_spell(fork.spell).await?)),
+                    None => None,
+                };
+                transaction.commit().await?;
+
+                let text = match fork {
+                    Some((position, spell)) => {
+                        let atom = parent
+                            .content
+                            .atom_text(position)
+                            .ok_or(ApiError::InvalidAtomPosition)?;
+                        game.ai().fork(&parent.content, atom, &spell).await?
+                    }
+                    None => game.ai().advance(&parent.content).await?,
+                };
+
+                let mut transaction = game.transaction().await?;
+                let (child_id, created) = transaction
+                    .insert_child_node(key, create_node_content(&text), created_by)
+                    .await?;
+                if created {
+                    transaction.publish(Event::NodeCreated {
+                        node_id: child_id,
+                        parent_id: key.parent_id,
+                    });
+                }
+                transaction.commit().await?;
+
+                Ok(child_id)
+            })
+            .await
+    }
+
     async fn initialize(&self) -> Result<(), Error> {
         const INITIALIZED: Uuid = uuid!("1c02e958-b74c-48f3-97e8-a7d5a8f53703");
 
@@ -137,14 +552,166 @@ impl Game {
             transaction.set_property(Some(INITIALIZED), &true).await?;
         }
 
+        // spells created by older versions don't have canonical names yet.
+        transaction.canonicalize_spell_names().await?;
+
+        // dev mode might have been turned on after the game was initialized.
+        if self.dev_mode() {
+            transaction.insert_test_user().await?;
+        }
+
         transaction.commit().await?;
 
         Ok(())
     }
 
+    /// Periodically deletes data that isn't needed anymore.
+    async fn continuously_delete_old_data(&self) {
+        let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            let result = async {
+                let mut transaction = self.transaction().await?;
+                let now = transaction.now();
+                let events = transaction
+                    .delete_events_before(now - self.inner.event_retention)
+                    .await?;
+                let access_tokens = transaction.delete_expired_access_tokens().await?;
+                let failed_logins = transaction.delete_old_failed_logins().await?;
+                let login_events = transaction
+                    .delete_login_events_before(now - self.inner.login_event_retention)
+                    .await?;
+                transaction.commit().await?;
+                Ok::<_, Error>((events, access_tokens, failed_logins, login_events))
+            }
+            .await;
+
+            match result {
+                Ok((events, access_tokens, failed_logins, login_events)) => {
+                    tracing::debug!(
+                        events,
+                        access_tokens,
+                        failed_logins,
+                        login_events,
+                        "deleted old data"
+                    )
+                }
+                Err(error) => tracing::error!(?error, "failed to delete old data"),
+            }
+        }
+    }
+
+    /// Periodically lets the other players know which users left by not being
+    /// active anymore.
+    async fn continuously_expire_presence(&self) {
+        let mut interval = tokio::time::interval(PRESENCE_EXPIRY_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            let result = async {
+                let mut transaction = self.transaction().await?;
+                let users = transaction.expire_presence().await?;
+                transaction.commit().await?;
+                Ok::<_, Error>(users)
+            }
+            .await;
+
+            match result {
+                Ok(users) => tracing::debug!(users, "expired presence"),
+                Err(error) => tracing::error!(?error, "failed to expire presence"),
+            }
+        }
+    }
+
+    /// Forwards events from all server instances to our subscribers.
+    ///
+    /// Notifications only tell us that there are new events. We always deliver
+    /// everything after the last event we delivered, so that nothing is lost
+    /// when notifications are missed while the listener reconnects, or when
+    /// fetching events fails.
+    async fn listen_for_events(&self, mut listener: PgListener, mut last_event_id: EventId) {
+        let mut caught_up = true;
+
+        loop {
+            if caught_up {
+                match listener.recv().await {
+                    Ok(notification) => {
+                        // events up to this one might have been delivered already.
+                        if notification
+                            .payload()
+                            .parse::<EventId>()
+                            .is_ok_and(|event_id| event_id <= last_event_id)
+                        {
+                            continue;
+                        }
+                    }
+                    Err(error) => {
+                        // the listener reconnects by itself, but notifications sent in the
+                        // meantime are lost.
+                        tracing::error!(?error, "failed to receive event notification");
+                        tokio::time::sleep(EVENT_LISTENER_RETRY_INTERVAL).await;
+                    }
+                }
+            }
+
+            match self.deliver_events_after(&mut last_event_id).await {
+                Ok(()) => caught_up = true,
+                Err(error) => {
+                    tracing::error!(?error, last_event_id, "failed to fetch events");
+                    tokio::time::sleep(EVENT_LISTENER_RETRY_INTERVAL).await;
+                    caught_up = false;
+                }
+            }
+        }
+    }
+
+    async fn deliver_events_after(&self, last_event_id: &mut EventId) -> Result<(), Error> {
+        loop {
+            let mut transaction = self.transaction().await?;
+            let events = transaction
+                .fetch_events_after(*last_event_id, EVENT_LISTENER_BATCH_SIZE)
+                .await?;
+            transaction.commit().await?;
+
+            if events.is_empty() {
+                return Ok(());
+            }
+
+            for (event_id, event) in events {
+                self.publish(event_id, event);
+                *last_event_id = event_id;
+            }
+        }
+    }
+
     async fn serve(self, address: SocketAddr) -> Result<(), Error> {
         let (session_layer, session_layer_task_abort_handle) =
-            session_layer(self.inner.pool.clone()).await?;
+            session_layer(self.inner.pool.clone(), self.inner.session_config.clone()).await?;
+
+        let mut listener = PgListener::connect_with(&self.inner.pool).await?;
+        listener.listen(NOTIFY_CHANNEL).await?;
+        // events that were logged before we started listening aren't delivered.
+        let mut transaction = self.transaction().await?;
+        let last_event_id = transaction.fetch_latest_event_id().await?;
+        transaction.commit().await?;
+        let event_listener_task_abort_handle = tokio::task::spawn({
+            let game = self.clone();
+            async move { game.listen_for_events(listener, last_event_id).await }
+        })
+        .abort_handle();
+
+        let cleanup_task_abort_handle = tokio::task::spawn({
+            let game = self.clone();
+            async move { game.continuously_delete_old_data().await }
+        })
+        .abort_handle();
+
+        let presence_task_abort_handle = tokio::task::spawn({
+            let game = self.clone();
+            async move { game.continuously_expire_presence().await }
+        })
+        .abort_handle();
 
         let router = Router::new()
             .nest("/api/v1", crate::api::routes())
@@ -156,15 +723,18 @@ impl Game {
         // run server until a shutdown signal is received
         axum::serve(
             TcpListener::bind(address).await?,
-            ServiceExt::<Request>::into_make_service(
+            ServiceExt::<Request>::into_make_service_with_connect_info::<SocketAddr>(
                 NormalizePathLayer::trim_trailing_slash().layer(router),
             ),
         )
         .with_graceful_shutdown(shutdown_signal())
         .await?;
 
-        // abort session layer cleanup task
+        // abort background tasks
         session_layer_task_abort_handle.abort();
+        cleanup_task_abort_handle.abort();
+        presence_task_abort_handle.abort();
+        event_listener_task_abort_handle.abort();
 
         Ok(())
     }
@@ -211,6 +781,7 @@ async fn shutdown_signal() {
 
 async fn session_layer(
     pool: PgPool,
+    config: SessionConfig,
 ) -> Result<(SessionManagerLayer<PostgresStore>, AbortHandle), Error> {
     let session_store = PostgresStore::new(pool)
         .with_schema_name("public")
@@ -227,8 +798,9 @@ async fn session_layer(
     );
 
     let session_layer = SessionManagerLayer::new(session_store)
-        .with_secure(false)
-        .with_expiry(Expiry::OnSessionEnd);
+        .with_secure(config.secure)
+        .with_same_site(config.same_site)
+        .with_expiry(config.expiry);
 
     Ok((session_layer, session_deletion_task.abort_handle()))
 }
@@ -248,10 +820,13 @@ pub struct Transaction<'a> {
     game: Game,
     transaction: sqlx::Transaction<'a, Postgres>,
     now: DateTime<Utc>,
+    /// Events that are logged and published when the transaction is committed.
+    pending_events: Vec<Event>,
 }
 
 impl<'a> Transaction<'a> {
-    pub async fn commit(self) -> Result<(), Error> {
+    pub async fn commit(mut self) -> Result<(), Error> {
+        self.log_pending_events().await?;
         self.transaction.commit().await?;
         Ok(())
     }
@@ -307,8 +882,35 @@ impl<'a> Transaction<'a> {
         let root_node = create_root_node(content);
         self.insert_node(&root_node).await?;
 
-        // create test user
-        let test_user_id = uuid!("43d65ac1-2778-49e8-b28d-65c7334cec32").into();
+        for (name, emoji) in INITIAL_SPELLS {
+            let spell = create_spell(
+                (*name).to_owned(),
+                (*emoji).to_owned(),
+                "placeholder".to_owned(),
+            );
+            self.insert_spell(&spell).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Creates the test user with some of each initial spell, unless it already
+    /// exists.
+    pub async fn insert_test_user(&mut self) -> Result<(), Error> {
+        let test_user_id = TEST_USER_ID.into();
+
+        let exists = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM users WHERE user_id = $1) AS "exists!""#,
+            TEST_USER_ID,
+        )
+        .fetch_one(self.db())
+        .await?;
+        if exists {
+            return Ok(());
+        }
+
+        tracing::info!("creating test user");
+
         self.insert_user(
             test_user_id,
             "test",
@@ -316,23 +918,11 @@ impl<'a> Transaction<'a> {
         )
         .await?;
 
-        const SPELLS: &[(&'static str, &'static str)] = &[
-            ("Wind", "🌬️"),
-            ("Earth", "🌍"),
-            ("Fire", "🔥"),
-            ("Water", "🌊"),
-        ];
-        for (name, emoji) in SPELLS {
-            let spell = create_spell(
-                (*name).to_owned(),
-                (*emoji).to_owned(),
-                "placeholder".to_owned(),
-            );
-            self.insert_spell(&spell).await?;
+        for (name, _) in INITIAL_SPELLS {
             self.add_to_inventory(
                 test_user_id,
                 SpellAmount {
-                    spell: spell.spell_id,
+                    spell: get_spell_id_for_name(name),
                     amount: 100,
                 },
             )
@@ -341,4 +931,9 @@ impl<'a> Transaction<'a> {
 
         Ok(())
     }
+
+    /// Whether the user is the test user while dev mode is off.
+    pub fn is_disabled_test_user(&self, user_id: UserId) -> bool {
+        user_id.0 == TEST_USER_ID && !self.game.dev_mode()
+    }
 }
@@ -1,11 +1,19 @@
-use chrono::NaiveDateTime;
+use chrono::{
+    DateTime,
+    NaiveDateTime,
+    Utc,
+};
+use futures::TryStreamExt;
 use lazy_static::lazy_static;
 use regex::Regex;
 use semantica_protocol::{
+    error::ApiError,
     node::{
         Atom,
         Content,
         Fork,
+        ForkLink,
+        MoveTarget,
         Node,
         NodeId,
         Paragraph,
@@ -39,6 +47,13 @@ use crate::{
 
 type CreateNode = Node<UserId, SpellId>;
 
+/// Identifies a child of a node, whether it exists yet or not.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ChildKey {
+    pub parent_id: NodeId,
+    pub fork: Option<Fork<SpellId>>,
+}
+
 pub fn create_root_node(content: Content) -> CreateNode {
     CreateNode {
         node_id: Uuid::new_v4().into(),
@@ -51,6 +66,29 @@ pub fn create_root_node(content: Content) -> CreateNode {
     }
 }
 
+/// Creates a child node. Without a fork, this is the natural child of the
+/// parent, i.e. the story just continues.
+pub fn create_child_node(
+    parent_id: NodeId,
+    fork: Option<Fork<SpellId>>,
+    content: Content,
+    created_by: UserId,
+    created_at: DateTime<Utc>,
+) -> CreateNode {
+    CreateNode {
+        node_id: Uuid::new_v4().into(),
+        parent: Some(ParentLink {
+            node_id: parent_id,
+            fork,
+        }),
+        natural_child: None,
+        fork_children: vec![],
+        created_at: Some(created_at),
+        created_by: Some(created_by),
+        content,
+    }
+}
+
 pub fn create_node_content(text: &str) -> Content {
     lazy_static! {
         static ref WORD: Regex = r"\b\w+\b".parse().unwrap();
@@ -64,7 +102,7 @@ pub fn create_node_content(text: &str) -> Content {
         if !line.is_empty() {
             let mut atoms = vec![];
 
-            for m in WORD.find_iter(&text) {
+            for m in WORD.find_iter(line) {
                 atoms.push(Atom {
                     start: m.start(),
                     length: m.len(),
@@ -158,8 +196,9 @@ impl FromDb<ResponseNode> for NodeRow {
                     })
                 })
                 .transpose()?,
-            natural_child: None,   // todo
-            fork_children: vec![], // todo
+            // children are fetched separately by `fetch_node_children`
+            natural_child: None,
+            fork_children: vec![],
             created_at: self.created_at.from_db()?,
             created_by: match (self.created_by_user_id, self.created_by_name) {
                 (Some(user_id), Some(name)) => {
@@ -177,10 +216,14 @@ impl FromDb<ResponseNode> for NodeRow {
 }
 
 impl<'a> Transaction<'a> {
-    pub async fn insert_node(&mut self, node: &CreateNode) -> Result<(), Error> {
+    /// Inserts a node.
+    ///
+    /// Returns `false` if the node wasn't inserted, because its parent already
+    /// has a child at this fork.
+    pub async fn insert_node(&mut self, node: &CreateNode) -> Result<bool, Error> {
         tracing::debug!(node_id = ?node.node_id, "inserting node");
 
-        sqlx::query!(
+        let result = sqlx::query!(
             r#"
             INSERT INTO nodes (
                 node_id,
@@ -199,6 +242,7 @@ impl<'a> Transaction<'a> {
                 $6,
                 $7
             )
+            ON CONFLICT DO NOTHING
             "#,
             ToDb::<Uuid>::to_db(&node.node_id)?,
             ToDb::<serde_json::Value>::to_db(&node.content)?,
@@ -211,6 +255,11 @@ impl<'a> Transaction<'a> {
         .execute(self.db())
         .await?;
 
+        if result.rows_affected() == 0 {
+            tracing::debug!(node_id = ?node.node_id, "node already exists");
+            return Ok(false);
+        }
+
         if node.parent.is_none() {
             tracing::debug!(node_id = ?node.node_id, "inserting node as root node");
 
@@ -222,14 +271,43 @@ impl<'a> Transaction<'a> {
             .await?;
         }
 
-        Ok(())
+        Ok(true)
+    }
+
+    /// Inserts a child node, unless the child already exists.
+    ///
+    /// Returns the ID of the child, and whether it was inserted.
+    pub async fn insert_child_node(
+        &mut self,
+        key: ChildKey,
+        content: Content,
+        created_by: UserId,
+    ) -> Result<(NodeId, bool), Error> {
+        let child = create_child_node(key.parent_id, key.fork, content, created_by, self.now());
+
+        if self.insert_node(&child).await? {
+            Ok((child.node_id, true))
+        }
+        else {
+            Ok((
+                self.fetch_child_id(&key).await?.unwrap_or_else(|| bug!()),
+                false,
+            ))
+        }
+    }
+
+    pub async fn fetch_child_id(&mut self, key: &ChildKey) -> Result<Option<NodeId>, Error> {
+        match &key.fork {
+            Some(fork) => self.fetch_fork_child_id(key.parent_id, fork).await,
+            None => self.fetch_natural_child_id(key.parent_id).await,
+        }
     }
 
     pub async fn fetch_current_user_node(
         &mut self,
         user_id: UserId,
     ) -> Result<ResponseNode, Error> {
-        Ok(sqlx::query_as!(
+        let mut node: ResponseNode = sqlx::query_as!(
             NodeRow,
             r#"
             SELECT
@@ -239,17 +317,17 @@ impl<'a> Transaction<'a> {
                 nodes.parent_position AS parent_position,
                 nodes.created_at AS created_at,
     
-                users_created_by.user_id AS created_by_user_id,
-                users_created_by.name AS created_by_name,
+                users_created_by.user_id AS "created_by_user_id?",
+                users_created_by.name AS "created_by_name?",
     
-                spells_created_with.spell_id AS created_with_spell_id,
-                spells_created_with.name AS created_with_name,
-                spells_created_with.emoji AS created_with_emoji,
-                spells_created_with.description AS created_with_description,
-                spells_created_with.created_at AS created_with_created_at,
+                spells_created_with.spell_id AS "created_with_spell_id?",
+                spells_created_with.name AS "created_with_name?",
+                spells_created_with.emoji AS "created_with_emoji?",
+                spells_created_with.description AS "created_with_description?",
+                spells_created_with.created_at AS "created_with_created_at?",
     
-                users_created_with_created_by.user_id AS created_with_created_by_user_id,
-                users_created_with_created_by.name AS created_with_created_by_name
+                users_created_with_created_by.user_id AS "created_with_created_by_user_id?",
+                users_created_with_created_by.name AS "created_with_created_by_name?"
             FROM users
                 INNER JOIN nodes ON users.in_node = nodes.node_id
                 LEFT OUTER JOIN users AS users_created_by ON nodes.created_by = users_created_by.user_id
@@ -259,11 +337,13 @@ impl<'a> Transaction<'a> {
             LIMIT 1
             "#,
             user_id.0,
-        ).fetch_one(self.db()).await?.from_db()?)
+        ).fetch_one(self.db()).await?.from_db()?;
+        self.fetch_node_children(&mut node).await?;
+        Ok(node)
     }
 
     pub async fn fetch_node(&mut self, node_id: NodeId) -> Result<ResponseNode, Error> {
-        Ok(sqlx::query_as!(
+        let mut node: ResponseNode = sqlx::query_as!(
             NodeRow,
             r#"
             SELECT
@@ -273,17 +353,17 @@ impl<'a> Transaction<'a> {
                 nodes.parent_position AS parent_position,
                 nodes.created_at AS created_at,
     
-                users_created_by.user_id AS created_by_user_id,
-                users_created_by.name AS created_by_name,
+                users_created_by.user_id AS "created_by_user_id?",
+                users_created_by.name AS "created_by_name?",
     
-                spells_created_with.spell_id AS created_with_spell_id,
-                spells_created_with.name AS created_with_name,
-                spells_created_with.emoji AS created_with_emoji,
-                spells_created_with.description AS created_with_description,
-                spells_created_with.created_at AS created_with_created_at,
+                spells_created_with.spell_id AS "created_with_spell_id?",
+                spells_created_with.name AS "created_with_name?",
+                spells_created_with.emoji AS "created_with_emoji?",
+                spells_created_with.description AS "created_with_description?",
+                spells_created_with.created_at AS "created_with_created_at?",
     
-                users_created_with_created_by.user_id AS created_with_created_by_user_id,
-                users_created_with_created_by.name AS created_with_created_by_name
+                users_created_with_created_by.user_id AS "created_with_created_by_user_id?",
+                users_created_with_created_by.name AS "created_with_created_by_name?"
             FROM nodes
                 LEFT OUTER JOIN users AS users_created_by ON nodes.created_by = users_created_by.user_id
                 LEFT OUTER JOIN spells AS spells_created_with ON nodes.created_with = spells_created_with.spell_id
@@ -292,6 +372,198 @@ impl<'a> Transaction<'a> {
             LIMIT 1
             "#,
             node_id.0,
-        ).fetch_one(self.db()).await?.from_db()?)
+        ).fetch_one(self.db()).await?.from_db()?;
+        self.fetch_node_children(&mut node).await?;
+        Ok(node)
+    }
+
+    /// Fills in the natural child and fork children of a node.
+    ///
+    /// All children are fetched with a single query, so this is fine for nodes
+    /// with lots of forks.
+    async fn fetch_node_children(&mut self, node: &mut ResponseNode) -> Result<(), Error> {
+        let mut rows = sqlx::query!(
+            r#"
+            SELECT
+                nodes.node_id AS node_id,
+                nodes.parent_position AS parent_position,
+
+                spells.spell_id AS "spell_id?",
+                spells.name AS "spell_name?",
+                spells.emoji AS "spell_emoji?",
+                spells.description AS "spell_description?",
+                spells.created_at AS spell_created_at,
+
+                users.user_id AS "spell_created_by_user_id?",
+                users.name AS "spell_created_by_name?"
+            FROM nodes
+                LEFT OUTER JOIN spells ON nodes.created_with = spells.spell_id
+                LEFT OUTER JOIN users ON spells.created_by = users.user_id
+            WHERE nodes.parent_id = $1
+            ORDER BY nodes.parent_position ASC
+            "#,
+            ToDb::<Uuid>::to_db(&node.node_id)?,
+        )
+        .fetch(self.db());
+
+        let mut natural_child = None;
+        let mut fork_children = vec![];
+
+        while let Some(row) = rows.try_next().await? {
+            let child_id: NodeId = row.node_id.from_db()?;
+
+            match (
+                row.parent_position,
+                row.spell_id,
+                row.spell_name,
+                row.spell_emoji,
+                row.spell_description,
+            ) {
+                (None, None, None, None, None) => {
+                    natural_child = Some(child_id);
+                }
+                (Some(position), Some(spell_id), Some(name), Some(emoji), Some(description)) => {
+                    let created_by = match (row.spell_created_by_user_id, row.spell_created_by_name)
+                    {
+                        (Some(user_id), Some(name)) => {
+                            Some(UserLink {
+                                user_id: user_id.from_db()?,
+                                name,
+                            })
+                        }
+                        (None, None) => None,
+                        _ => bug!(),
+                    };
+                    fork_children.push(ForkLink {
+                        node_id: child_id,
+                        fork: Fork {
+                            position: position.from_db()?,
+                            spell: Spell {
+                                spell_id: spell_id.from_db()?,
+                                name,
+                                emoji,
+                                description,
+                                created_at: row.spell_created_at.from_db()?,
+                                created_by,
+                            },
+                        },
+                    });
+                }
+                _ => bug!(),
+            }
+        }
+
+        node.natural_child = natural_child;
+        node.fork_children = fork_children;
+
+        Ok(())
+    }
+
+    pub async fn fetch_fork_child_id(
+        &mut self,
+        node_id: NodeId,
+        fork: &Fork<SpellId>,
+    ) -> Result<Option<NodeId>, Error> {
+        Ok(sqlx::query_scalar!(
+            r#"
+            SELECT node_id
+            FROM nodes
+            WHERE parent_id = $1 AND parent_position = $2 AND created_with = $3
+            "#,
+            ToDb::<Uuid>::to_db(&node_id)?,
+            ToDb::<i32>::to_db(&fork.position)?,
+            ToDb::<Uuid>::to_db(&fork.spell)?,
+        )
+        .fetch_optional(self.db())
+        .await?
+        .from_db()?)
+    }
+
+    pub async fn fetch_natural_child_id(
+        &mut self,
+        node_id: NodeId,
+    ) -> Result<Option<NodeId>, Error> {
+        Ok(sqlx::query_scalar!(
+            "SELECT node_id FROM nodes WHERE parent_id = $1 AND parent_position IS NULL",
+            ToDb::<Uuid>::to_db(&node_id)?,
+        )
+        .fetch_optional(self.db())
+        .await?
+        .from_db()?)
+    }
+
+    /// Moves a user to an adjacent node.
+    ///
+    /// Returns the node the user left and the node they entered.
+    pub async fn move_user(
+        &mut self,
+        user_id: UserId,
+        target: MoveTarget,
+    ) -> Result<(NodeId, NodeId), Error> {
+        let current_node_id: NodeId = sqlx::query_scalar!(
+            "SELECT in_node FROM users WHERE user_id = $1 FOR UPDATE",
+            ToDb::<Uuid>::to_db(&user_id)?,
+        )
+        .fetch_one(self.db())
+        .await?
+        .from_db()?;
+
+        let target_node_id: NodeId = match target {
+            MoveTarget::Parent => {
+                sqlx::query_scalar!(
+                    "SELECT parent_id FROM nodes WHERE node_id = $1",
+                    ToDb::<Uuid>::to_db(&current_node_id)?,
+                )
+                .fetch_one(self.db())
+                .await?
+                .from_db()?
+                // root nodes have no parent to move to.
+                .ok_or(ApiError::NotAdjacent)?
+            }
+            MoveTarget::NaturalChild => {
+                // the story doesn't continue until someone advances it.
+                self.fetch_natural_child_id(current_node_id)
+                    .await?
+                    .ok_or(ApiError::NotAdjacent)?
+            }
+            MoveTarget::ForkChild(node_id) => {
+                sqlx::query_scalar!(
+                    r#"
+                    SELECT node_id
+                    FROM nodes
+                    WHERE node_id = $1 AND parent_id = $2 AND parent_position IS NOT NULL
+                    "#,
+                    ToDb::<Uuid>::to_db(&node_id)?,
+                    ToDb::<Uuid>::to_db(&current_node_id)?,
+                )
+                .fetch_optional(self.db())
+                .await?
+                .from_db()?
+                .ok_or(ApiError::NotAdjacent)?
+            }
+        };
+
+        tracing::debug!(?user_id, from = ?current_node_id, to = ?target_node_id, "moving user");
+
+        sqlx::query!(
+            "UPDATE users SET in_node = $1 WHERE user_id = $2",
+            ToDb::<Uuid>::to_db(&target_node_id)?,
+            ToDb::<Uuid>::to_db(&user_id)?,
+        )
+        .execute(self.db())
+        .await?;
+
+        Ok((current_node_id, target_node_id))
+    }
+
+    pub async fn fetch_user_node_id(&mut self, user_id: UserId) -> Result<NodeId, Error> {
+        let node_id = sqlx::query_scalar!(
+            "SELECT in_node FROM users WHERE user_id = $1",
+            ToDb::<Uuid>::to_db(&user_id)?,
+        )
+        .fetch_one(self.db())
+        .await?
+        .from_db()?;
+        Ok(node_id)
     }
 }
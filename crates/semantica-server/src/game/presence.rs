@@ -0,0 +1,195 @@
+use chrono::Duration;
+use semantica_protocol::{
+    event::Event,
+    node::NodeId,
+    user::{
+        UserId,
+        UserLink,
+    },
+};
+
+use super::Transaction;
+use crate::error::Error;
+
+/// How long users count as present after their last request.
+const PRESENCE_TIMEOUT_MINUTES: i64 = 5;
+
+/// How often a user's activity is recorded.
+const ACTIVITY_RESOLUTION_SECONDS: i64 = 60;
+
+impl<'a> Transaction<'a> {
+    /// Records that the user is active.
+    ///
+    /// If the user was gone for too long, the other players see them enter
+    /// again.
+    pub async fn touch_user(&mut self, user_id: UserId) -> Result<(), Error> {
+        let returned = sqlx::query!(
+            r#"
+            UPDATE users SET present = TRUE
+            WHERE user_id = $1 AND NOT present
+            RETURNING in_node, name, visible
+            "#,
+            user_id.0,
+        )
+        .fetch_optional(self.db())
+        .await?;
+
+        if let Some(row) = returned {
+            self.publish(Event::PlayerEntered {
+                node_id: row.in_node.into(),
+                user: UserLink {
+                    user_id,
+                    name: row.name,
+                },
+                hidden: !row.visible,
+            });
+        }
+
+        let now = self.now();
+        sqlx::query!(
+            "UPDATE users SET last_active = $1 WHERE user_id = $2 AND last_active < $3",
+            now.naive_utc(),
+            user_id.0,
+            (now - Duration::seconds(ACTIVITY_RESOLUTION_SECONDS)).naive_utc(),
+        )
+        .execute(self.db())
+        .await?;
+        Ok(())
+    }
+
+    /// Marks users that weren't active recently as gone, so that the other
+    /// players see them leave.
+    ///
+    /// Returns how many users left.
+    pub async fn expire_presence(&mut self) -> Result<usize, Error> {
+        let active_since = self.now() - Duration::minutes(PRESENCE_TIMEOUT_MINUTES);
+        let rows = sqlx::query!(
+            r#"
+            UPDATE users SET present = FALSE
+            WHERE present AND last_active < $1
+            RETURNING user_id, in_node, name, visible
+            "#,
+            active_since.naive_utc(),
+        )
+        .fetch_all(self.db())
+        .await?;
+
+        let count = rows.len();
+        for row in rows {
+            self.publish(Event::PlayerLeft {
+                node_id: row.in_node.into(),
+                user: UserLink {
+                    user_id: row.user_id.into(),
+                    name: row.name,
+                },
+                hidden: !row.visible,
+            });
+        }
+
+        Ok(count)
+    }
+
+    pub async fn fetch_user_visible(&mut self, user_id: UserId) -> Result<bool, Error> {
+        let visible =
+            sqlx::query_scalar!("SELECT visible FROM users WHERE user_id = $1", user_id.0)
+                .fetch_one(self.db())
+                .await?;
+        Ok(visible)
+    }
+
+    /// Sets whether other users can see where the user is.
+    ///
+    /// Returns whether the visibility changed.
+    pub async fn set_user_visible(
+        &mut self,
+        user_id: UserId,
+        visible: bool,
+    ) -> Result<bool, Error> {
+        let result = sqlx::query!(
+            "UPDATE users SET visible = $1 WHERE user_id = $2 AND visible <> $1",
+            visible,
+            user_id.0,
+        )
+        .execute(self.db())
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Fetches the users that were recently active in a node.
+    ///
+    /// Invisible users are left out, unless it's the user asking.
+    pub async fn fetch_node_presence(
+        &mut self,
+        node_id: NodeId,
+        user_id: UserId,
+    ) -> Result<Vec<UserLink>, Error> {
+        let active_since = self.now() - Duration::minutes(PRESENCE_TIMEOUT_MINUTES);
+        let users = sqlx::query!(
+            r#"
+            SELECT user_id, name
+            FROM users
+            WHERE in_node = $1 AND last_active >= $2 AND (visible OR user_id = $3)
+            ORDER BY name
+            "#,
+            node_id.0,
+            active_since.naive_utc(),
+            user_id.0,
+        )
+        .fetch_all(self.db())
+        .await?
+        .into_iter()
+        .map(|row| {
+            UserLink {
+                user_id: row.user_id.into(),
+                name: row.name,
+            }
+        })
+        .collect();
+        Ok(users)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::game::Game;
+
+    #[sqlx::test(migrations = false)]
+    async fn it_announces_inactive_users_as_leaving(pool: PgPool) {
+        let game = Game::new_for_test(pool).await.unwrap();
+        let (user_id, _) = game.create_user_for_test("alice").await.unwrap();
+
+        let mut transaction = game.transaction().await.unwrap();
+        let latest_event_id = transaction.fetch_latest_event_id().await.unwrap();
+        let node_id = transaction.fetch_user_node_id(user_id).await.unwrap();
+        let inactive_since = transaction.now() - Duration::minutes(PRESENCE_TIMEOUT_MINUTES + 1);
+        sqlx::query!(
+            "UPDATE users SET last_active = $1 WHERE user_id = $2",
+            inactive_since.naive_utc(),
+            user_id.0,
+        )
+        .execute(transaction.db())
+        .await
+        .unwrap();
+        assert_eq!(transaction.expire_presence().await.unwrap(), 1);
+        // the user is only announced once.
+        assert_eq!(transaction.expire_presence().await.unwrap(), 0);
+        transaction.commit().await.unwrap();
+
+        let mut transaction = game.transaction().await.unwrap();
+        let events = transaction
+            .fetch_events_after(latest_event_id, 100)
+            .await
+            .unwrap();
+        transaction.commit().await.unwrap();
+        assert!(events.iter().any(|(_, event)| {
+            matches!(
+                event,
+                Event::PlayerLeft { node_id: id, user, hidden: false }
+                    if *id == node_id && user.user_id == user_id
+            )
+        }));
+    }
+}
@@ -0,0 +1,311 @@
+use std::collections::HashMap;
+
+use chrono::{
+    DateTime,
+    NaiveDateTime,
+    Utc,
+};
+use futures::TryStreamExt;
+use murmur3::Murmur3x64x128;
+use semantica_protocol::{
+    pagination::Pagination,
+    spell::{
+        KnownRecipe,
+        Recipe,
+        RecipeId,
+        SpellId,
+    },
+    user::UserId,
+};
+use uuid::Uuid;
+
+use super::Transaction;
+use crate::{
+    error::Error,
+    utils::convert::{
+        FromDb,
+        ToDb,
+    },
+};
+
+/// Upper limit for the page size when fetching known recipes.
+const MAX_RECIPES_PER_PAGE: usize = 100;
+
+/// The product of a recipe.
+#[derive(Clone, Copy, Debug)]
+pub struct CraftedProduct {
+    pub product_id: SpellId,
+    /// The user who crafted the recipe first, if it was crafted just now.
+    pub discovered_by: Option<UserId>,
+}
+
+/// Derives the recipe id from its ingredients. The order of the ingredients
+/// doesn't matter, but how often a spell appears does.
+pub fn get_recipe_id_for_ingredients(ingredients: &[SpellId]) -> RecipeId {
+    const SEED: u32 = 2;
+
+    let mut ingredients = ingredients.to_vec();
+    ingredients.sort();
+
+    let mut data = Vec::with_capacity(ingredients.len() * 16);
+    for spell_id in &ingredients {
+        data.extend_from_slice(spell_id.0.as_bytes());
+    }
+
+    let hash = murmur3::hash::<Murmur3x64x128, _>(SEED, data.as_slice());
+    Uuid::from_u128(hash).into()
+}
+
+impl<'a> Transaction<'a> {
+    /// Returns the product of a recipe, if the recipe is known.
+    pub async fn fetch_recipe_product(
+        &mut self,
+        recipe_id: RecipeId,
+    ) -> Result<Option<SpellId>, Error> {
+        Ok(sqlx::query_scalar!(
+            "SELECT product FROM recipes WHERE recipe_id = $1",
+            ToDb::<Uuid>::to_db(&recipe_id)?,
+        )
+        .fetch_optional(self.db())
+        .await?
+        .flatten()
+        .from_db()?)
+    }
+
+    /// Creates a recipe without product, if it doesn't exist yet, and locks it
+    /// for the rest of the transaction.
+    ///
+    /// If another transaction is crafting the same recipe, this waits for it to
+    /// finish, and then returns its product. If this returns `None`, it's up to
+    /// this transaction to set the product with [`Self::set_recipe_product`].
+    pub async fn lock_recipe(
+        &mut self,
+        recipe_id: RecipeId,
+        ingredients: &[SpellId],
+    ) -> Result<Option<SpellId>, Error> {
+        let mut ingredients = ingredients
+            .iter()
+            .map(|spell_id| spell_id.to_db())
+            .collect::<Result<Vec<Uuid>, _>>()?;
+        ingredients.sort();
+
+        Ok(sqlx::query_scalar!(
+            r#"
+            INSERT INTO recipes (
+                recipe_id,
+                product,
+                ingredients
+            ) VALUES ($1, NULL, $2)
+            ON CONFLICT (recipe_id)
+                DO UPDATE SET recipe_id = EXCLUDED.recipe_id
+            RETURNING product
+            "#,
+            ToDb::<Uuid>::to_db(&recipe_id)?,
+            &ingredients,
+        )
+        .fetch_one(self.db())
+        .await?
+        .from_db()?)
+    }
+
+    pub async fn set_recipe_product(
+        &mut self,
+        recipe_id: RecipeId,
+        product: SpellId,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            "UPDATE recipes SET product = $1 WHERE recipe_id = $2",
+            ToDb::<Uuid>::to_db(&product)?,
+            ToDb::<Uuid>::to_db(&recipe_id)?,
+        )
+        .execute(self.db())
+        .await?;
+        Ok(())
+    }
+
+    /// Replaces spells in the ingredients of recipes.
+    ///
+    /// The recipes are moved to the IDs derived from their new ingredients,
+    /// together with the users who know them. Recipes that end up with the same
+    /// ingredients are merged.
+    pub async fn replace_recipe_ingredients(
+        &mut self,
+        replacements: &HashMap<SpellId, SpellId>,
+    ) -> Result<(), Error> {
+        let replaced_ids = replacements
+            .keys()
+            .map(|spell_id| spell_id.to_db())
+            .collect::<Result<Vec<Uuid>, _>>()?;
+
+        let rows = sqlx::query!(
+            "SELECT recipe_id, product, ingredients FROM recipes WHERE ingredients && $1",
+            &replaced_ids,
+        )
+        .fetch_all(self.db())
+        .await?;
+
+        for row in rows {
+            let ingredients = row
+                .ingredients
+                .into_iter()
+                .map(|spell_id| {
+                    let spell_id: SpellId = spell_id.from_db()?;
+                    Ok(replacements.get(&spell_id).copied().unwrap_or(spell_id))
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            let recipe_id = get_recipe_id_for_ingredients(&ingredients);
+
+            let mut ingredients = ingredients
+                .iter()
+                .map(|spell_id| spell_id.to_db())
+                .collect::<Result<Vec<Uuid>, _>>()?;
+            ingredients.sort();
+
+            sqlx::query!(
+                r#"
+                INSERT INTO recipes (
+                    recipe_id,
+                    product,
+                    ingredients
+                ) VALUES ($1, $2, $3)
+                ON CONFLICT (recipe_id)
+                    DO UPDATE SET product = COALESCE(recipes.product, EXCLUDED.product)
+                "#,
+                ToDb::<Uuid>::to_db(&recipe_id)?,
+                row.product,
+                &ingredients,
+            )
+            .execute(self.db())
+            .await?;
+
+            sqlx::query!(
+                r#"
+                INSERT INTO known_recipes (
+                    recipe_id,
+                    user_id,
+                    created_at
+                )
+                SELECT $1, user_id, created_at
+                FROM known_recipes
+                WHERE recipe_id = $2
+                ON CONFLICT (recipe_id, user_id) DO NOTHING
+                "#,
+                ToDb::<Uuid>::to_db(&recipe_id)?,
+                row.recipe_id,
+            )
+            .execute(self.db())
+            .await?;
+
+            sqlx::query!(
+                "DELETE FROM known_recipes WHERE recipe_id = $1",
+                row.recipe_id,
+            )
+            .execute(self.db())
+            .await?;
+
+            sqlx::query!("DELETE FROM recipes WHERE recipe_id = $1", row.recipe_id)
+                .execute(self.db())
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Adds a recipe to the user's recipe book. Does nothing if the user
+    /// already knows the recipe.
+    pub async fn insert_known_recipe(
+        &mut self,
+        user_id: UserId,
+        recipe_id: RecipeId,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO known_recipes (
+                recipe_id,
+                user_id,
+                created_at
+            ) VALUES ($1, $2, $3)
+            ON CONFLICT (recipe_id, user_id) DO NOTHING
+            "#,
+            ToDb::<Uuid>::to_db(&recipe_id)?,
+            ToDb::<Uuid>::to_db(&user_id)?,
+            ToDb::<NaiveDateTime>::to_db(&self.now())?,
+        )
+        .execute(self.db())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Fetches the recipes a user has discovered, most recent first.
+    pub async fn fetch_known_recipes(
+        &mut self,
+        user_id: UserId,
+        pagination: Pagination,
+    ) -> Result<Vec<KnownRecipe>, Error> {
+        let mut rows = sqlx::query!(
+            r#"
+            SELECT
+                known_recipes.recipe_id AS recipe_id,
+                known_recipes.created_at AS discovered_at,
+                recipes.product AS product,
+                recipes.ingredients AS ingredients
+            FROM known_recipes
+                INNER JOIN recipes ON known_recipes.recipe_id = recipes.recipe_id
+            WHERE known_recipes.user_id = $1
+            ORDER BY known_recipes.created_at DESC, known_recipes.recipe_id ASC
+            OFFSET $2
+            LIMIT $3
+            "#,
+            ToDb::<Uuid>::to_db(&user_id)?,
+            ToDb::<i64>::to_db(&pagination.offset)?,
+            ToDb::<i64>::to_db(&pagination.limit.min(MAX_RECIPES_PER_PAGE))?,
+        )
+        .fetch(self.db());
+
+        let mut recipes: Vec<(Recipe<SpellId>, DateTime<Utc>)> = vec![];
+        while let Some(row) = rows.try_next().await? {
+            recipes.push((
+                Recipe {
+                    recipe_id: row.recipe_id.from_db()?,
+                    ingredients: row
+                        .ingredients
+                        .into_iter()
+                        .map(|spell_id| spell_id.from_db())
+                        .collect::<Result<_, _>>()?,
+                    product: row.product.from_db()?,
+                },
+                row.discovered_at.from_db()?,
+            ));
+        }
+        drop(rows);
+
+        // resolve all spells with one query
+        let spell_ids = recipes
+            .iter()
+            .flat_map(|(recipe, _)| recipe.ingredients.iter().chain(&recipe.product))
+            .copied()
+            .collect::<Vec<_>>();
+        let spells = self.fetch_spells(&spell_ids).await?;
+
+        Ok(recipes
+            .into_iter()
+            .map(|(recipe, discovered_at)| {
+                KnownRecipe {
+                    recipe: Recipe {
+                        recipe_id: recipe.recipe_id,
+                        ingredients: recipe
+                            .ingredients
+                            .iter()
+                            .filter_map(|spell_id| spells.get(spell_id).cloned())
+                            .collect(),
+                        product: recipe
+                            .product
+                            .and_then(|spell_id| spells.get(&spell_id).cloned()),
+                    },
+                    discovered_at,
+                }
+            })
+            .collect())
+    }
+}
@@ -1,4 +1,10 @@
+use std::collections::{
+    hash_map::Entry,
+    HashMap,
+};
+
 use chrono::NaiveDateTime;
+use futures::TryStreamExt;
 use murmur3::Murmur3x64x128;
 use semantica_protocol::{
     spell::{
@@ -17,13 +23,26 @@ use uuid::Uuid;
 use super::Transaction;
 use crate::{
     error::Error,
-    utils::convert::{
-        DbConversionError,
-        FromDb,
-        ToDb,
+    utils::{
+        bug,
+        convert::{
+            DbConversionError,
+            FromDb,
+            ToDb,
+        },
     },
 };
 
+/// Returns the name by which spells are identified.
+///
+/// Names that only differ in case or whitespace refer to the same spell.
+pub fn canonicalize_spell_name(name: &str) -> String {
+    name.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
 pub fn get_spell_id_for_name(name: &str) -> SpellId {
     const SEED: u32 = 1;
     let hash = murmur3::hash::<Murmur3x64x128, _>(SEED, name.as_bytes());
@@ -51,14 +70,16 @@ impl<'a> Transaction<'a> {
             INSERT INTO spells (
                 spell_id,
                 name,
+                canonical_name,
                 emoji,
                 description,
                 created_at,
                 created_by
-            ) VALUES ($1, $2, $3, $4, $5, $6)
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7)
             "#,
             ToDb::<Uuid>::to_db(&spell.spell_id)?,
             &spell.name,
+            canonicalize_spell_name(&spell.name),
             &spell.emoji,
             &spell.description,
             ToDb::<Option<NaiveDateTime>>::to_db(&spell.created_at)?,
@@ -69,11 +90,72 @@ impl<'a> Transaction<'a> {
         Ok(())
     }
 
+    /// Inserts a spell, unless a spell with the same canonical name already
+    /// exists.
+    ///
+    /// Returns the ID of the spell with that name, and whether it was inserted.
+    pub async fn insert_or_get_spell<CreatedBy: Links<UserId>>(
+        &mut self,
+        spell: &Spell<CreatedBy>,
+    ) -> Result<(SpellId, bool), Error> {
+        let canonical_name = canonicalize_spell_name(&spell.name);
+
+        let inserted = sqlx::query!(
+            r#"
+            INSERT INTO spells (
+                spell_id,
+                name,
+                canonical_name,
+                emoji,
+                description,
+                created_at,
+                created_by
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT DO NOTHING
+            "#,
+            ToDb::<Uuid>::to_db(&spell.spell_id)?,
+            &spell.name,
+            &canonical_name,
+            &spell.emoji,
+            &spell.description,
+            ToDb::<Option<NaiveDateTime>>::to_db(&spell.created_at)?,
+            ToDb::<Option<Uuid>>::to_db(&spell.created_by.as_ref().map(|link| link.id()))?,
+        )
+        .execute(self.db())
+        .await?
+        .rows_affected()
+            > 0;
+
+        if inserted {
+            return Ok((spell.spell_id, true));
+        }
+
+        let row = sqlx::query!(
+            "SELECT spell_id FROM spells WHERE canonical_name = $1",
+            &canonical_name,
+        )
+        .fetch_one(self.db())
+        .await?;
+
+        Ok((row.spell_id.from_db()?, false))
+    }
+
     pub async fn fetch_spell(&mut self, spell_id: SpellId) -> Result<Spell<UserLink>, Error> {
-        // todo: join to get created_by.name
         let spell = sqlx::query_as!(
             SpellRow,
-            "SELECT * FROM spells WHERE spell_id = $1",
+            r#"
+            SELECT
+                spells.spell_id AS spell_id,
+                spells.name AS name,
+                spells.emoji AS emoji,
+                spells.description AS description,
+                spells.created_at AS created_at,
+                users.user_id AS "created_by_user_id?",
+                users.name AS "created_by_name?"
+            FROM spells
+                LEFT OUTER JOIN users ON spells.created_by = users.user_id
+            WHERE spells.spell_id = $1
+            "#,
             ToDb::<Uuid>::to_db(&spell_id)?
         )
         .fetch_one(self.db())
@@ -81,6 +163,176 @@ impl<'a> Transaction<'a> {
         .from_db()?;
         Ok(spell)
     }
+
+    pub async fn fetch_spells(
+        &mut self,
+        spell_ids: &[SpellId],
+    ) -> Result<HashMap<SpellId, Spell<UserLink>>, Error> {
+        let spell_ids = spell_ids
+            .iter()
+            .map(|spell_id| spell_id.to_db())
+            .collect::<Result<Vec<Uuid>, _>>()?;
+
+        let mut rows = sqlx::query_as!(
+            SpellRow,
+            r#"
+            SELECT
+                spells.spell_id AS spell_id,
+                spells.name AS name,
+                spells.emoji AS emoji,
+                spells.description AS description,
+                spells.created_at AS created_at,
+                users.user_id AS "created_by_user_id?",
+                users.name AS "created_by_name?"
+            FROM spells
+                LEFT OUTER JOIN users ON spells.created_by = users.user_id
+            WHERE spells.spell_id = ANY($1)
+            "#,
+            &spell_ids,
+        )
+        .fetch(self.db());
+
+        let mut spells = HashMap::with_capacity(spell_ids.len());
+        while let Some(row) = rows.try_next().await? {
+            let spell: Spell<UserLink> = row.from_db()?;
+            spells.insert(spell.spell_id, spell);
+        }
+
+        Ok(spells)
+    }
+
+    /// Sets the canonical names of spells that were created before spells were
+    /// identified by them, and merges spells with the same canonical name.
+    ///
+    /// The oldest of the merged spells is kept. Base spells have no creation
+    /// time and are always kept.
+    pub async fn canonicalize_spell_names(&mut self) -> Result<(), Error> {
+        let has_missing_names = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM spells WHERE canonical_name IS NULL) AS "exists!""#
+        )
+        .fetch_one(self.db())
+        .await?;
+        if !has_missing_names {
+            return Ok(());
+        }
+
+        // no spells must be created while we merge them.
+        sqlx::query!("LOCK TABLE spells IN SHARE ROW EXCLUSIVE MODE")
+            .execute(self.db())
+            .await?;
+
+        let rows = sqlx::query!(
+            r#"
+            SELECT spell_id, name, canonical_name
+            FROM spells
+            ORDER BY created_at ASC NULLS FIRST, spell_id
+            "#
+        )
+        .fetch_all(self.db())
+        .await?;
+
+        let mut kept = HashMap::with_capacity(rows.len());
+        let mut duplicates = HashMap::new();
+        let mut missing_names = vec![];
+
+        for row in rows {
+            let spell_id: SpellId = row.spell_id.from_db()?;
+            let is_missing = row.canonical_name.is_none();
+            let canonical_name = row
+                .canonical_name
+                .unwrap_or_else(|| canonicalize_spell_name(&row.name));
+
+            match kept.entry(canonical_name) {
+                Entry::Occupied(entry) => {
+                    duplicates.insert(spell_id, *entry.get());
+                }
+                Entry::Vacant(entry) => {
+                    if is_missing {
+                        missing_names.push((spell_id, entry.key().clone()));
+                    }
+                    entry.insert(spell_id);
+                }
+            }
+        }
+
+        tracing::info!(
+            spells = missing_names.len(),
+            duplicates = duplicates.len(),
+            "canonicalizing spell names"
+        );
+
+        for (&duplicate_id, &spell_id) in &duplicates {
+            let duplicate_id = ToDb::<Uuid>::to_db(&duplicate_id)?;
+            let spell_id = ToDb::<Uuid>::to_db(&spell_id)?;
+
+            sqlx::query!(
+                "UPDATE nodes SET created_with = $1 WHERE created_with = $2",
+                spell_id,
+                duplicate_id,
+            )
+            .execute(self.db())
+            .await?;
+
+            sqlx::query!(
+                "UPDATE recipes SET product = $1 WHERE product = $2",
+                spell_id,
+                duplicate_id,
+            )
+            .execute(self.db())
+            .await?;
+
+            sqlx::query!(
+                r#"
+                INSERT INTO inventory_contents (
+                    user_id,
+                    spell_id,
+                    amount
+                )
+                SELECT user_id, $1, amount
+                FROM inventory_contents
+                WHERE spell_id = $2
+                ON CONFLICT (user_id, spell_id)
+                    DO UPDATE SET amount = inventory_contents.amount + EXCLUDED.amount
+                "#,
+                spell_id,
+                duplicate_id,
+            )
+            .execute(self.db())
+            .await?;
+
+            sqlx::query!(
+                "DELETE FROM inventory_contents WHERE spell_id = $1",
+                duplicate_id,
+            )
+            .execute(self.db())
+            .await?;
+        }
+
+        self.replace_recipe_ingredients(&duplicates).await?;
+
+        let duplicate_ids = duplicates
+            .keys()
+            .map(|spell_id| spell_id.to_db())
+            .collect::<Result<Vec<Uuid>, _>>()?;
+        sqlx::query!(
+            "DELETE FROM spells WHERE spell_id = ANY($1)",
+            &duplicate_ids
+        )
+        .execute(self.db())
+        .await?;
+
+        for (spell_id, canonical_name) in missing_names {
+            sqlx::query!(
+                "UPDATE spells SET canonical_name = $1 WHERE spell_id = $2",
+                canonical_name,
+                ToDb::<Uuid>::to_db(&spell_id)?,
+            )
+            .execute(self.db())
+            .await?;
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(FromRow)]
@@ -90,11 +342,146 @@ struct SpellRow {
     emoji: String,
     description: String,
     created_at: Option<NaiveDateTime>,
-    created_by: Option<Uuid>,
+    created_by_user_id: Option<Uuid>,
+    created_by_name: Option<String>,
 }
 
 impl FromDb<Spell<UserLink>> for SpellRow {
     fn from_db(self) -> Result<Spell<UserLink>, DbConversionError> {
-        todo!();
+        Ok(Spell {
+            spell_id: self.spell_id.from_db()?,
+            name: self.name,
+            emoji: self.emoji,
+            description: self.description,
+            created_at: self.created_at.from_db()?,
+            created_by: match (self.created_by_user_id, self.created_by_name) {
+                (Some(user_id), Some(name)) => {
+                    Some(UserLink {
+                        user_id: user_id.from_db()?,
+                        name,
+                    })
+                }
+                (None, None) => None,
+                _ => bug!(),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use semantica_protocol::spell::SpellAmount;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::game::{
+        recipe::get_recipe_id_for_ingredients,
+        Game,
+    };
+
+    /// Inserts a spell like older versions did, without a canonical name.
+    async fn insert_old_spell(
+        transaction: &mut Transaction<'_>,
+        name: &str,
+        age: Duration,
+    ) -> SpellId {
+        let spell_id = get_spell_id_for_name(name);
+        sqlx::query!(
+            r#"
+            INSERT INTO spells (
+                spell_id,
+                name,
+                emoji,
+                description,
+                created_at
+            ) VALUES ($1, $2, '🔥', '', $3)
+            "#,
+            ToDb::<Uuid>::to_db(&spell_id).unwrap(),
+            name,
+            (transaction.now() - age).naive_utc(),
+        )
+        .execute(transaction.db())
+        .await
+        .unwrap();
+        spell_id
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_merges_spells_with_the_same_canonical_name(pool: PgPool) {
+        let game = Game::new_for_test(pool).await.unwrap();
+        let (user_id, _) = game.create_user_for_test("alice").await.unwrap();
+        let fire = get_spell_id_for_name("Fire");
+        let water = get_spell_id_for_name("Water");
+
+        let mut transaction = game.transaction().await.unwrap();
+        let steam = insert_old_spell(&mut transaction, "steam", Duration::days(2)).await;
+        let duplicate_steam = insert_old_spell(&mut transaction, "Steam ", Duration::days(1)).await;
+        let duplicate_fire = insert_old_spell(&mut transaction, "fire", Duration::days(1)).await;
+
+        let old_ingredients = [duplicate_fire, water];
+        let old_recipe_id = get_recipe_id_for_ingredients(&old_ingredients);
+        transaction
+            .lock_recipe(old_recipe_id, &old_ingredients)
+            .await
+            .unwrap();
+        transaction
+            .set_recipe_product(old_recipe_id, duplicate_steam)
+            .await
+            .unwrap();
+        transaction
+            .insert_known_recipe(user_id, old_recipe_id)
+            .await
+            .unwrap();
+
+        for spell_id in [fire, duplicate_fire] {
+            transaction
+                .add_to_inventory(
+                    user_id,
+                    SpellAmount {
+                        spell: spell_id,
+                        amount: 1,
+                    },
+                )
+                .await
+                .unwrap();
+        }
+
+        transaction.canonicalize_spell_names().await.unwrap();
+
+        let recipe_id = get_recipe_id_for_ingredients(&[fire, water]);
+        assert_eq!(
+            transaction.fetch_recipe_product(recipe_id).await.unwrap(),
+            Some(steam)
+        );
+        assert_eq!(
+            transaction
+                .fetch_recipe_product(old_recipe_id)
+                .await
+                .unwrap(),
+            None
+        );
+
+        let known_recipe_ids = sqlx::query_scalar!(
+            "SELECT recipe_id FROM known_recipes WHERE user_id = $1",
+            ToDb::<Uuid>::to_db(&user_id).unwrap(),
+        )
+        .fetch_all(transaction.db())
+        .await
+        .unwrap();
+        assert_eq!(known_recipe_ids, [ToDb::<Uuid>::to_db(&recipe_id).unwrap()]);
+
+        let inventory = transaction.fetch_inventory(user_id).await.unwrap();
+        assert_eq!(inventory.len(), 1);
+        assert_eq!(inventory[0].spell.spell_id, fire);
+        assert_eq!(inventory[0].amount, 2);
+
+        let spells = transaction
+            .fetch_spells(&[steam, duplicate_steam, duplicate_fire])
+            .await
+            .unwrap();
+        assert_eq!(spells.keys().collect::<Vec<_>>(), [&steam]);
+
+        transaction.commit().await.unwrap();
     }
 }
@@ -7,6 +7,7 @@ use chrono::{
     Utc,
 };
 use semantica_protocol::{
+    auth::LoginMethod,
     node::NodeId,
     spell::{
         RecipeId,
@@ -27,6 +28,9 @@ pub enum DbConversionError {
 
     #[error("json")]
     Json(#[from] serde_json::Error),
+
+    #[error("invalid value for {ty}: {value}")]
+    InvalidValue { ty: &'static str, value: String },
 }
 
 impl From<Infallible> for DbConversionError {
@@ -111,6 +115,36 @@ macro_rules! impl_number {
 }
 
 impl_number!(usize, i32);
+impl_number!(usize, i64);
+
+impl FromDb<LoginMethod> for String {
+    fn from_db(self) -> Result<LoginMethod, DbConversionError> {
+        match self.as_str() {
+            "register" => Ok(LoginMethod::Register),
+            "secret" => Ok(LoginMethod::Secret),
+            "device" => Ok(LoginMethod::Device),
+            "password" => Ok(LoginMethod::Password),
+            _ => {
+                Err(DbConversionError::InvalidValue {
+                    ty: "LoginMethod",
+                    value: self,
+                })
+            }
+        }
+    }
+}
+
+impl ToDb<String> for LoginMethod {
+    fn to_db(&self) -> Result<String, DbConversionError> {
+        let method = match self {
+            LoginMethod::Register => "register",
+            LoginMethod::Secret => "secret",
+            LoginMethod::Device => "device",
+            LoginMethod::Password => "password",
+        };
+        Ok(method.to_owned())
+    }
+}
 
 impl<T: for<'de> Deserialize<'de>> FromDb<T> for serde_json::Value {
     fn from_db(self) -> Result<T, DbConversionError> {
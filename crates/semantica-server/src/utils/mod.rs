@@ -1,4 +1,5 @@
 pub mod convert;
+pub mod single_flight;
 
 macro_rules! bug {
     () => {{
@@ -0,0 +1,98 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    future::Future,
+    hash::Hash,
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+use futures::{
+    future::{
+        BoxFuture,
+        Shared,
+    },
+    FutureExt,
+};
+
+use crate::error::Error;
+
+type Pending<V> = Shared<BoxFuture<'static, Result<V, Arc<Error>>>>;
+
+/// Deduplicates concurrent computations with the same key.
+///
+/// The first caller for a key spawns the computation. Everyone else calling
+/// with the same key while it's still running waits for the same result. The
+/// computation runs to completion, even if all callers went away in the
+/// meantime.
+///
+/// The key is forgotten as soon as the computation finishes, so it must store
+/// its result (e.g. commit it to the database) before then. Otherwise a caller
+/// that comes in between finds neither the result nor the computation, and
+/// starts it again.
+pub struct SingleFlight<K, V> {
+    pending: Arc<Mutex<HashMap<K, Pending<V>>>>,
+}
+
+impl<K, V> Default for SingleFlight<K, V> {
+    fn default() -> Self {
+        Self {
+            pending: Default::default(),
+        }
+    }
+}
+
+impl<K, V> Debug for SingleFlight<K, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let num_pending = self.pending.lock().unwrap().len();
+        f.debug_struct("SingleFlight")
+            .field("num_pending", &num_pending)
+            .finish()
+    }
+}
+
+impl<K, V> SingleFlight<K, V>
+where
+    K: Clone + Eq + Hash + Debug + Send + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    /// Runs `future`, unless a computation for `key` is already running. Then
+    /// `future` is dropped and the result of the running computation is
+    /// returned instead.
+    pub async fn run(
+        &self,
+        key: K,
+        future: impl Future<Output = Result<V, Error>> + Send + 'static,
+    ) -> Result<V, Error> {
+        let pending = {
+            let mut pending = self.pending.lock().unwrap();
+
+            if let Some(pending) = pending.get(&key) {
+                tracing::debug!(?key, "waiting for pending computation");
+                pending.clone()
+            }
+            else {
+                let task = tokio::task::spawn({
+                    let pending = self.pending.clone();
+                    let key = key.clone();
+                    async move {
+                        let result = future.await.map_err(Arc::new);
+                        pending.lock().unwrap().remove(&key);
+                        result
+                    }
+                });
+
+                let shared = task
+                    .map(|result| result.map_err(|error| Arc::new(error.into()))?)
+                    .boxed()
+                    .shared();
+                pending.insert(key, shared.clone());
+                shared
+            }
+        };
+
+        pending.await.map_err(Error::Shared)
+    }
+}